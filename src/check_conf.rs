@@ -0,0 +1,232 @@
+// Offline validation of the config file. Runs the same
+// checks as `deployer run` plus extra ones that would
+// otherwise only fail during a deploy.
+
+use crate::generate_conf::file_struct::{ConfigFile, Service};
+use crate::run_deployer::pull::svc;
+use crate::run_deployer::{config_error::ConfigError, deserialise, validate};
+use std::{
+    fs,
+    path::{Component, Path},
+};
+
+#[derive(Debug, PartialEq)]
+enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug)]
+struct Diagnostic {
+    severity: Severity,
+    message: String,
+}
+
+impl Diagnostic {
+    fn error(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            message,
+        }
+    }
+
+    fn warning(message: String) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            message,
+        }
+    }
+}
+
+/// Checks the config file without contacting GitHub and
+/// prints all errors and warnings found.
+///
+/// Returns exit code to be returned by the process,
+/// non-zero if there is at least one error.
+pub fn check(config_path: &str) -> i32 {
+    let config = match deserialise(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("error: {}", e);
+            return 1;
+        }
+    };
+
+    let mut diagnostics = Vec::new();
+    if let Err(ConfigError::Invalid(problems)) = validate(&config) {
+        diagnostics.extend(problems.into_iter().map(Diagnostic::error));
+    }
+    for service in &config.services {
+        diagnostics.extend(check_build_dir(service));
+        diagnostics.extend(check_service(service));
+    }
+    diagnostics.extend(check_units(&config));
+
+    let mut errors = 0;
+    for d in &diagnostics {
+        match d.severity {
+            Severity::Error => {
+                errors += 1;
+                println!("error: {}", d.message);
+            }
+            Severity::Warning => println!("warning: {}", d.message),
+        }
+    }
+    if diagnostics.is_empty() {
+        println!("The config file is valid.");
+    } else {
+        println!(
+            "Found {} error(s) and {} warning(s).",
+            errors,
+            diagnostics.len() - errors
+        );
+    }
+    if errors > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// `build_dir` must exist and be writable, as the
+/// build is moved there.
+fn check_build_dir(service: &Service) -> Option<Diagnostic> {
+    let dir = &service.build_dir;
+    if !dir.is_dir() {
+        return Some(Diagnostic::error(format!(
+            "`{}.build_dir`: \"{}\" does not exist.",
+            service.name,
+            dir.display()
+        )));
+    }
+    let probe = dir.join(format!(".deployer-check-{}", std::process::id()));
+    match fs::write(&probe, b"") {
+        Ok(()) => {
+            _ = fs::remove_file(&probe);
+            None
+        }
+        Err(e) => Some(Diagnostic::error(format!(
+            "`{}.build_dir`: \"{}\" is not writable: {}",
+            service.name,
+            dir.display(),
+            e
+        ))),
+    }
+}
+
+/// Checks of a service that do not touch the file system.
+fn check_service(service: &Service) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    let name = &service.name;
+
+    if let Some(custom_dir) = &service.custom_dir {
+        let path = Path::new(custom_dir);
+        if path.is_absolute() {
+            diagnostics.push(Diagnostic::error(format!(
+                "`{}.custom_dir`: \"{}\" must be relative to the repository root.",
+                name, custom_dir
+            )));
+        }
+        if path.components().any(|c| c == Component::ParentDir) {
+            diagnostics.push(Diagnostic::error(format!(
+                "`{}.custom_dir`: \"{}\" must not contain \"..\".",
+                name, custom_dir
+            )));
+        }
+    }
+
+    if !service.svc_filename.ends_with(".service") {
+        diagnostics.push(Diagnostic::error(format!(
+            "`{}.svc_filename`: \"{}\" does not end with \".service\".",
+            name, service.svc_filename
+        )));
+    }
+
+    let has_section = |section: &str| {
+        service
+            .svc_file_contents
+            .iter()
+            .any(|line| line.trim() == section)
+    };
+    if !has_section("[Service]") {
+        diagnostics.push(Diagnostic::error(format!(
+            "`{}.svc_file_contents`: there is no [Service] section.",
+            name
+        )));
+    }
+    if !has_section("[Unit]") {
+        diagnostics.push(Diagnostic::warning(format!(
+            "`{}.svc_file_contents`: there is no [Unit] section.",
+            name
+        )));
+    }
+    diagnostics
+}
+
+/// Unit files are only written if they do not exist, so
+/// warn about the ones that differ from the config.
+fn check_units(config: &ConfigFile) -> Vec<Diagnostic> {
+    let svc_dir = &config.sys_svc_dir;
+    config
+        .services
+        .iter()
+        .filter_map(|service| {
+            let current = svc::read_unit(&service.svc_filename, svc_dir).ok()??;
+            if current == svc::unit_contents(&service.svc_file_contents) {
+                return None;
+            }
+            Some(Diagnostic::warning(format!(
+                "`{}.svc_file_contents`: {} differs from the config, \
+                run `deployer overwrite` to apply it.",
+                service.name, service.svc_filename
+            )))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service() -> Service {
+        Service {
+            svc_file_contents: vec![
+                String::from("[Unit]"),
+                String::from("Description=Test"),
+                String::from("[Service]"),
+                String::from("ExecStart=/bin/true"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_valid_service() {
+        assert!(check_service(&service()).is_empty());
+    }
+
+    #[test]
+    fn test_invalid_custom_dir() {
+        let mut service = service();
+        service.custom_dir = Some(String::from("/backend/../v2"));
+        let diagnostics = check_service(&service);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+    }
+
+    #[test]
+    fn test_invalid_unit() {
+        let mut service = service();
+        service.svc_filename = String::from("backend");
+        service.svc_file_contents = vec![String::from("[Unit]")];
+        let diagnostics = check_service(&service);
+        assert_eq!(diagnostics.len(), 2);
+        assert!(diagnostics[0].message.contains("\".service\""));
+        assert!(diagnostics[1].message.contains("[Service]"));
+
+        service.svc_file_contents = vec![String::from("[Service]")];
+        let diagnostics = check_service(&service);
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[1].severity, Severity::Warning);
+    }
+}
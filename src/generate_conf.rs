@@ -1,12 +1,131 @@
 use chrono::{DateTime, Local};
 use std::fs::OpenOptions;
 use crate::log;
+use serde_json::Value;
 use std::{
     fs::File,
     io::{Result, Write},
 };
 
 pub mod file_struct;
+pub mod jsonc;
+
+/// Comments written above the fields of the generated config file.
+/// Fields of `ConfigFile` and `Service` share this list.
+const FIELD_DOCS: &[(&str, &str)] = &[
+    (
+        "provider",
+        "Service hosting the repository: \"github\", \"gitlab\" or \"gitea\" (also for Forgejo).\n\
+        \"git\" works with any git remote (https://, ssh:// or file://) without using an API,\n\
+        `repository` is then the URL passed to git as is and `token` is optional.",
+    ),
+    (
+        "repository",
+        "Link to the repository, the \"https://\" part is optional. It is cloned over SSH\n\
+        if given as \"git@github.com:owner/repo.git\".\n\
+        GitLab repositories may be in subgroups: \"gitlab.example.com/group/subgroup/repo\".",
+    ),
+    (
+        "api_url",
+        "Base URL of the provider's API, for example \"https://git.example.com/api/v1\".\n\
+        Nullable, https://api.github.com, https://<host>/api/v4 (GitLab) or\n\
+        https://<host>/api/v1 (Gitea) is used if not set.",
+    ),
+    ("branch", "Branch to check for new commits."),
+    (
+        "token",
+        "Token used to check for new commits and to pull the repository.\n\
+        Use \"${VAR}\" to read it from environment variable VAR. If neither `token`\n\
+        nor `token_file` is set, it is read from DEPLOYER_TOKEN environment variable.",
+    ),
+    (
+        "token_file",
+        "File containing the token (e.g. systemd credential or Docker secret).\n\
+        Nullable, used instead of `token`. May be relative to this file.",
+    ),
+    (
+        "github_app",
+        "GitHub App to authenticate as instead of using `token`. Nullable, otherwise\n\
+        { \"app_id\": 1, \"installation_id\": 2, \"private_key\": \"app.private-key.pem\" }.",
+    ),
+    (
+        "ssh_key",
+        "Private key to clone over SSH with, e.g. a read-only deploy key. Nullable,\n\
+        required if `repository` is an SSH URL such as \"git@github.com:owner/repo.git\".",
+    ),
+    ("ssh_passphrase", "Passphrase of `ssh_key`. Nullable."),
+    (
+        "known_hosts",
+        "Host keys SSH remotes are verified against. Nullable, ~/.ssh/known_hosts if not set.",
+    ),
+    (
+        "webhook",
+        "Listener for GitHub push webhooks, new commits are deployed as soon as they are pushed\n\
+        and checked for every 10 minutes by default. Nullable, otherwise\n\
+        { \"address\": \"0.0.0.0:8080\", \"secret\": \"secret set on GitHub\" }.",
+    ),
+    (
+        "poll_interval",
+        "Seconds between checks for new commits. Nullable, 60 (or 600 with `webhook`) if not set.\n\
+        Checks are spread randomly by 10% and slowed down while they fail.",
+    ),
+    (
+        "pull_dir",
+        "Directory the repository is pulled into. Paths may be relative to\n\
+        this file and may start with ~ or $HOME.",
+    ),
+    (
+        "persistent_clone",
+        "Keep a single clone of the repository in `pull_dir` (named after the repository)\n\
+        and fetch new commits into it instead of cloning each one into a new directory.",
+    ),
+    (
+        "keep_pulls",
+        "Old pull directories to remove after every deploy (also `deployer gc`). Nullable,\n\
+        otherwise { \"count\": 5, \"max_age_days\": 30 }, either may be null. The newest one is kept.",
+    ),
+    (
+        "shallow_clone",
+        "Fetch only the commit being deployed, without the history. Not supported for local repositories.",
+    ),
+    (
+        "sparse_checkout",
+        "Check out only `custom_dir` of the services and `shared_paths`, not the whole repository.\n\
+        Every service needs `custom_dir` then.",
+    ),
+    (
+        "shared_paths",
+        "Paths within the repository the services need besides their `custom_dir`\n\
+        (e.g. [\"libs/common\"]). Only used with `sparse_checkout`.",
+    ),
+    (
+        "submodules",
+        "Initialise and update submodules recursively, fetched with the same credentials.",
+    ),
+    (
+        "sys_svc_dir",
+        "Directory with unit files of the service manager.",
+    ),
+    ("services", "Services to build and run from the repository."),
+    (
+        "name",
+        "Name of the service, the build is moved to `build_dir/name`.",
+    ),
+    (
+        "svc_filename",
+        "Unit file of the service within `sys_svc_dir`.",
+    ),
+    ("build_dir", "Directory the build is moved into."),
+    (
+        "custom_dir",
+        "Directory of the service within the repository, for example \"backend/v2\".\n\
+        Nullable, the whole repository is searched for the service if not set.",
+    ),
+    (
+        "svc_file_contents",
+        "Lines of the unit file. It is written if it does not exist yet.",
+    ),
+];
 
 /// Generate config file if it does not exist.
 /// Any `user_path` is valid but blank.
@@ -40,13 +159,19 @@ fn create_file(path: &str) -> Result<File> {
 /// Used in `generate(user_path: &str)` function
 /// to format path to the file.
 pub fn validate_path(path: &mut String) {
+    fmt_path(path);
+    log!("path: {}", path);
+}
+
+/// Same as `validate_path` but does not log the result,
+/// so it does not mess up output of the commands.
+pub fn fmt_path(path: &mut String) {
     if !path.ends_with("deployer-config.jsonc") {
         if !path.ends_with("/") {
-            path.push_str("/");
+            path.push('/');
         }
         path.push_str("deployer-config.jsonc");
     }
-    log!("path: {}", path);
 }
 
 /// This function is for generating default deployer
@@ -57,9 +182,62 @@ pub fn validate_path(path: &mut String) {
 /// to serialise structure into json or to write the data
 /// into the file.
 fn write_config(file: &mut File) -> Result<()> {
-    let config_file = file_struct::ConfigFile::default();
-    let data = serde_json::to_string_pretty(&config_file)?;
-    writeln!(file, "{}", data)?;
+    writeln!(file, "{}", template()?)?;
     file.flush()?;
     Ok(())
 }
+
+/// Default config serialised as JSONC with
+/// every field explained by a comment.
+fn template() -> Result<String> {
+    let config_file = file_struct::ConfigFile::default();
+    let value = serde_json::to_value(&config_file)?;
+    let mut out = String::new();
+    render(&value, 0, &mut out)?;
+    Ok(out)
+}
+
+fn render(value: &Value, indent: usize, out: &mut String) -> Result<()> {
+    let pad = " ".repeat(indent + 2);
+    match value {
+        Value::Object(map) => {
+            out.push_str("{\n");
+            for (i, (key, value)) in map.iter().enumerate() {
+                if let Some((_, doc)) = FIELD_DOCS.iter().find(|(k, _)| k == key) {
+                    for line in doc.lines() {
+                        out.push_str(&format!("{}// {}\n", pad, line));
+                    }
+                }
+                out.push_str(&format!("{}{}: ", pad, serde_json::to_string(key)?));
+                render(value, indent + 2, out)?;
+                out.push_str(if i + 1 < map.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&format!("{}}}", " ".repeat(indent)));
+        }
+        Value::Array(items) if !items.is_empty() => {
+            out.push_str("[\n");
+            for (i, item) in items.iter().enumerate() {
+                out.push_str(&pad);
+                render(item, indent + 2, out)?;
+                out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+            }
+            out.push_str(&format!("{}]", " ".repeat(indent)));
+        }
+        _ => out.push_str(&serde_json::to_string(value)?),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use file_struct::ConfigFile;
+
+    #[test]
+    fn test_template_is_valid_jsonc() {
+        let data = template().unwrap();
+        assert!(data.contains("// Branch to check for new commits."));
+        let config: ConfigFile = serde_json::from_str(&jsonc::strip(&data)).unwrap();
+        assert_eq!(config, ConfigFile::default());
+    }
+}
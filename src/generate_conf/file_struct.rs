@@ -1,26 +1,161 @@
 use serde_derive::{Deserialize, Serialize};
+use std::fmt::{Debug, Display};
+use std::path::PathBuf;
 
-#[derive(Debug, Deserialize)]
-pub struct Commit {
-    pub sha: String,
+/// Value that must never end up in the logs.
+/// Both `Debug` and `Display` print `***`.
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: String) -> Self {
+        Secret(value)
+    }
+
+    /// The actual value, to be passed on to the provider only.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Debug for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret(***)")
+    }
+}
+
+impl Display for Secret {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "***")
+    }
+}
+
+/// Service hosting the repository.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Provider {
+    #[default]
+    GitHub,
+    GitLab,
+    /// Gitea and its forks such as Forgejo.
+    Gitea,
+    /// Any git remote, new commits are detected
+    /// with `git ls-remote` instead of an API.
+    Git,
 }
 
+/// Written next to every build so it is known
+/// which commit is deployed and since when.
 #[derive(Debug, Serialize, Deserialize)]
+pub struct Deployment {
+    pub sha: String,
+    pub deployed_at: String,
+    /// Pull directory the build was made from, kept by `deployer gc`.
+    /// Not known for builds deployed by older versions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pull_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Service {
     pub name: String,
     pub svc_filename: String,
-    pub build_dir: String,
+    pub build_dir: PathBuf,
     pub custom_dir: Option<String>,
     pub svc_file_contents: Vec<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// GitHub App installed on the repository's owner.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GitHubApp {
+    pub app_id: u64,
+    pub installation_id: u64,
+    /// Private key of the app (PEM) generated on GitHub.
+    pub private_key: PathBuf,
+}
+
+/// Embedded HTTP listener for GitHub push webhooks.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Webhook {
+    /// Address to listen on, e.g. `0.0.0.0:8080`.
+    pub address: String,
+    /// Secret set in the webhook's settings on GitHub.
+    pub secret: Secret,
+}
+
+/// Which timestamped pull directories are kept.
+/// The newest one is always kept.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeepPulls {
+    /// Number of the newest pull directories to keep.
+    #[serde(default)]
+    pub count: Option<usize>,
+    /// Pull directories older than this many days are removed.
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct ConfigFile {
+    #[serde(default)]
+    pub provider: Provider,
     pub repository: String,
+    /// Base URL of the provider's API, derived
+    /// from `repository` if not set.
+    #[serde(default)]
+    pub api_url: Option<String>,
     pub branch: String,
-    pub token: String,
-    pub pull_dir: String,
-    pub sys_svc_dir: String,
+    /// Either the token itself or `${VAR}` to read it from
+    /// environment variable `VAR`. Once the config is loaded,
+    /// it holds the resolved token.
+    #[serde(default)]
+    pub token: Option<Secret>,
+    /// File containing the token, used if `token` is not set.
+    #[serde(default)]
+    pub token_file: Option<PathBuf>,
+    /// GitHub App to authenticate as instead of using `token`.
+    #[serde(default)]
+    pub github_app: Option<GitHubApp>,
+    /// Private key to clone over SSH with, e.g. a read-only deploy key.
+    #[serde(default)]
+    pub ssh_key: Option<PathBuf>,
+    #[serde(default)]
+    pub ssh_passphrase: Option<Secret>,
+    /// Host keys of SSH remotes, `~/.ssh/known_hosts` if not set.
+    #[serde(default)]
+    pub known_hosts: Option<PathBuf>,
+    /// Listener for push webhooks.
+    #[serde(default)]
+    pub webhook: Option<Webhook>,
+    /// Seconds between checks for new commits, 60 (or 600
+    /// with `webhook` set) if not set.
+    #[serde(default)]
+    pub poll_interval: Option<u64>,
+    pub pull_dir: PathBuf,
+    /// Keep one clone in `pull_dir` and fetch new commits into it
+    /// instead of cloning every commit into a new directory.
+    #[serde(default)]
+    pub persistent_clone: bool,
+    /// Old pull directories are removed after every deploy
+    /// if set. All of them are kept otherwise.
+    #[serde(default)]
+    pub keep_pulls: Option<KeepPulls>,
+    /// Fetch only the commit being deployed, without history.
+    #[serde(default)]
+    pub shallow_clone: bool,
+    /// Check out only `custom_dir` of the services
+    /// and `shared_paths` instead of the whole repository.
+    #[serde(default)]
+    pub sparse_checkout: bool,
+    /// Paths within the repository the services need besides
+    /// their `custom_dir`, e.g. shared libraries.
+    #[serde(default)]
+    pub shared_paths: Vec<String>,
+    /// Initialise and update submodules recursively.
+    #[serde(default)]
+    pub submodules: bool,
+    pub sys_svc_dir: PathBuf,
     pub services: Vec<Service>,
 }
 
@@ -29,7 +164,7 @@ impl Default for Service {
         Service {
             name: "service-name".to_owned(),
             svc_filename: "service-filename.service".to_owned(),
-            build_dir: "/var/www/my_service".to_owned(),
+            build_dir: PathBuf::from("/var/www/my_service"),
             custom_dir: None,
             svc_file_contents: vec!["[Unit]".to_owned(), "Description=Your desc".to_owned()],
         }
@@ -39,12 +174,34 @@ impl Default for Service {
 impl Default for ConfigFile {
     fn default() -> Self {
         ConfigFile {
+            provider: Provider::default(),
+            api_url: None,
             branch: "main".to_owned(),
             repository: "https://github.com/your-repository/link".to_owned(),
-            token: "YOUR-GITHUB-TOKEN-HERE".to_owned(),
-            pull_dir: "/var/www".to_owned(),
-            sys_svc_dir: "/lib/systemd/system".to_owned(),
+            token: Some(Secret::new("YOUR-GITHUB-TOKEN-HERE".to_owned())),
+            token_file: None,
+            github_app: None,
+            ssh_key: None,
+            ssh_passphrase: None,
+            known_hosts: None,
+            webhook: None,
+            poll_interval: None,
+            pull_dir: PathBuf::from("/var/www"),
+            persistent_clone: false,
+            keep_pulls: None,
+            shallow_clone: false,
+            sparse_checkout: false,
+            shared_paths: Vec::new(),
+            submodules: false,
+            sys_svc_dir: PathBuf::from("/lib/systemd/system"),
             services: vec![Service::default()],
         }
     }
 }
+
+impl ConfigFile {
+    /// Resolved GitHub token, empty if there is none.
+    pub fn token(&self) -> &str {
+        self.token.as_ref().map(Secret::expose).unwrap_or_default()
+    }
+}
@@ -0,0 +1,105 @@
+// JSON with comments, the format of `deployer-config.jsonc`.
+// Comments and trailing commas are blanked out, so the
+// result is plain JSON with the same line and column
+// numbers as the original text.
+
+/// Replaces `//` and `/* */` comments and trailing
+/// commas outside of strings with whitespace.
+pub fn strip(input: &str) -> String {
+    let mut out: Vec<char> = Vec::with_capacity(input.len());
+    let mut commas = Vec::new();
+    let mut chars = input.chars().peekable();
+    let mut in_string = false;
+
+    while let Some(c) = chars.next() {
+        if in_string {
+            out.push(c);
+            match c {
+                '\\' => out.extend(chars.next()),
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                out.push(c);
+            }
+            ('/', Some('/')) => {
+                out.push(' ');
+                while let Some(&next) = chars.peek() {
+                    if next == '\n' {
+                        break;
+                    }
+                    out.push(blank(next));
+                    chars.next();
+                }
+            }
+            ('/', Some('*')) => {
+                out.push(' ');
+                out.push(' ');
+                chars.next();
+                let mut prev = '\0';
+                for next in chars.by_ref() {
+                    out.push(blank(next));
+                    if prev == '*' && next == '/' {
+                        break;
+                    }
+                    prev = next;
+                }
+            }
+            (',', _) => {
+                commas.push(out.len());
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+
+    for i in commas {
+        let next = out[i + 1..].iter().find(|c| !c.is_whitespace());
+        if matches!(next, Some('}') | Some(']')) {
+            out[i] = ' ';
+        }
+    }
+    out.into_iter().collect()
+}
+
+/// Keeps line breaks so that line numbers do not shift.
+fn blank(c: char) -> char {
+    if c == '\n' {
+        '\n'
+    } else {
+        ' '
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_strip_comments() {
+        let input = "{\n  // comment\n  \"a\": 1, /* block\n comment */ \"b\": 2\n}";
+        let value: serde_json::Value = serde_json::from_str(&strip(input)).unwrap();
+        assert_eq!(value, serde_json::json!({ "a": 1, "b": 2 }));
+        assert_eq!(strip(input).lines().count(), input.lines().count());
+    }
+
+    #[test]
+    fn test_strip_trailing_commas() {
+        let input = "{ \"a\": [1, 2, ], \"b\": { \"c\": null, }, }";
+        let value: serde_json::Value = serde_json::from_str(&strip(input)).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!({ "a": [1, 2], "b": { "c": null } })
+        );
+    }
+
+    #[test]
+    fn test_keep_comments_in_strings() {
+        let input = r#"{ "url": "https://github.com/a/b", "s": "/* x */ \" // y," }"#;
+        assert_eq!(strip(input), input);
+    }
+}
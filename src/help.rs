@@ -10,6 +10,10 @@ pub fn help() {
             name: "config <path>",
             description: "\tGenerate config file.",
         },
+        Command {
+            name: "config check [path]",
+            description: "Validate config file without running Deployer.",
+        },
         Command {
             name: "run <path to config>",
             description: "Start Deployer.",
@@ -28,26 +32,38 @@ pub fn help() {
         },
         Command {
             name: "reload",
-            description: "\t\tReload config file to apply new configuration.",
+            description: "\t\tReload config file to apply new configuration
+            \t\t\t\t  without restarting running Deployer.",
+        },
+        Command {
+            name: "gc",
+            description: "\t\tRemove old pull directories as `keep_pulls`
+            \t\t\t\t  in the config file says.",
         },
         // Sort of dashboard where you'd see all services and their statuses
         // so you don't get lost which service is working and which is not.
         // Useful, especially for microservices.
         Command {
             name: "services status",
-            description: "Display all specified services' statuses.",
+            description: "Display all specified services' statuses.
+            \t\t\t\t  Use --json for machine-readable output.",
         },
         Command {
             name: "overwrite",
             description: "\tCompletely deletes all .service files
             \t\t\t\t  (matched with services in the config file)
-            \t\t\t\t  and replaces them with the new ones.",
+            \t\t\t\t  and replaces them with the new ones.
+            \t\t\t\t  Use -y to skip the confirmation.",
         },
     ];
 
     println!("Available commands:");
     for c in commands {
         print!("\tdeployer {}", c.name);
-        print!("\t{}\n", c.description);
+        println!("\t{}", c.description);
     }
+    println!(
+        "\nCommands other than config and run read deployer-config.jsonc from the\n\
+        current directory unless path to the config is passed as their last argument."
+    );
 }
@@ -1,5 +1,11 @@
+use std::{env, sync::OnceLock};
+
 pub const HELP_MSG: &str = "Use flag --help to see the documentation.";
 
+/// Environment variable enabling `debug!` messages
+/// if it is set to `debug`.
+pub const LOG_ENV: &str = "DEPLOYER_LOG";
+
 /// Check if length of arguments matches
 /// the minimal required number of arguments and
 /// return with help command printed if it does not.
@@ -33,6 +39,22 @@ macro_rules! log {
     }
 }
 
+/// Same as `log!`, but only printed if `DEPLOYER_LOG`
+/// environment variable is set to `debug`.
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        if $crate::macros::debug_enabled() {
+            $crate::log!($($arg)*);
+        }
+    }
+}
+
+pub fn debug_enabled() -> bool {
+    static ENABLED: OnceLock<bool> = OnceLock::new();
+    *ENABLED.get_or_init(|| env::var(LOG_ENV).is_ok_and(|v| v.eq_ignore_ascii_case("debug")))
+}
+
 #[macro_export]
 macro_rules! trust_me {
     ($x:expr) => {
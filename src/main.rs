@@ -1,8 +1,10 @@
-use std::{env, io::ErrorKind};
+use std::{env, io::ErrorKind, process};
 
+mod check_conf;
 mod generate_conf;
 mod help;
 mod macros;
+mod manage_svc;
 mod run_deployer;
 
 #[tokio::main]
@@ -14,12 +16,23 @@ async fn main() {
         "--help" => help::help(),
         "config" => handle_generate(&args),
         "run" => handle_run(&args).await,
+        "start" => handle_svc(&args, manage_svc::start),
+        "stop" => handle_svc(&args, manage_svc::stop),
+        "restart" => handle_svc(&args, manage_svc::restart),
+        "services" => handle_services(&args),
+        "overwrite" => handle_overwrite(&args),
+        "reload" => handle_reload(&args).await,
+        "gc" => process::exit(run_deployer::pull::gc::run(&config_path(&args, 2))),
         _ => println!("{}", macros::HELP_MSG),
     }
 }
 
 fn handle_generate(args: &[String]) {
     arg_len!(args.len(), 3, macros::HELP_MSG);
+    if args[2] == "check" {
+        let path = config_path(args, 3);
+        process::exit(check_conf::check(&path));
+    }
     match generate_conf::generate(&args[2]) {
         Ok(()) => println!("Created successfully."),
         Err(e) => match e.kind() {
@@ -35,3 +48,44 @@ async fn handle_run(args: &[String]) {
     generate_conf::validate_path(&mut path);
     run_deployer::run(&path).await;
 }
+
+/// Runs service command that takes `<service> [path to config]`
+/// and exits with the code it returned.
+fn handle_svc(args: &[String], command: fn(&str, &str) -> i32) {
+    arg_len!(args.len(), 3, macros::HELP_MSG);
+    let path = config_path(args, 3);
+    process::exit(command(&path, &args[2]));
+}
+
+fn handle_services(args: &[String]) {
+    arg_len!(args.len(), 3, macros::HELP_MSG);
+    if args[2] != "status" {
+        println!("{}", macros::HELP_MSG);
+        return;
+    }
+    let json = args[3..].iter().any(|a| a == "--json");
+    let rest: Vec<String> = args.iter().filter(|a| *a != "--json").cloned().collect();
+    let path = config_path(&rest, 3);
+    process::exit(manage_svc::status(&path, json));
+}
+
+async fn handle_reload(args: &[String]) {
+    let path = config_path(args, 2);
+    process::exit(run_deployer::control::reload(&path).await);
+}
+
+fn handle_overwrite(args: &[String]) {
+    let is_flag = |a: &String| a == "-y" || a == "--yes";
+    let yes = args.iter().any(is_flag);
+    let rest: Vec<String> = args.iter().filter(|a| !is_flag(a)).cloned().collect();
+    let path = config_path(&rest, 2);
+    process::exit(manage_svc::overwrite(&path, yes));
+}
+
+/// Path to the config file passed at `index`.
+/// Defaults to the current directory.
+fn config_path(args: &[String], index: usize) -> String {
+    let mut path = args.get(index).cloned().unwrap_or(String::from("."));
+    generate_conf::fmt_path(&mut path);
+    path
+}
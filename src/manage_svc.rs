@@ -0,0 +1,338 @@
+// Commands that manage services specified in the config file
+// by their logical name (`Service::name`) rather than by
+// their unit filename.
+
+use crate::generate_conf::file_struct::{ConfigFile, Service};
+use crate::run_deployer::deserialise;
+use crate::run_deployer::pull::{record, restart_logic, svc, ServiceInfo};
+use chrono::Local;
+use serde_derive::Serialize;
+use similar::TextDiff;
+use std::{
+    io::{self, Result, Write},
+    process::ExitStatus,
+};
+
+/// One row of `deployer services status`.
+#[derive(Debug, Serialize)]
+struct ServiceStatus {
+    name: String,
+    unit: String,
+    active_state: String,
+    sub_state: String,
+    main_pid: Option<u32>,
+    uptime_secs: Option<i64>,
+    commit: Option<String>,
+    deployed_at: Option<String>,
+}
+
+/// Looks the service up by its name in the config file,
+/// writes its unit file if it is missing and starts it.
+///
+/// Returns exit code to be returned by the process.
+pub fn start(config_path: &str, name: &str) -> i32 {
+    let Some(config) = read_config(config_path) else {
+        return 1;
+    };
+    let Some(service) = find_service(&config, name) else {
+        return not_found(name);
+    };
+    let status = svc::start_service(
+        &service.svc_filename,
+        &config.sys_svc_dir,
+        &service.svc_file_contents,
+    );
+    report("start", service, status)
+}
+
+/// Stops the service found by its name in the config file.
+///
+/// Returns exit code to be returned by the process.
+pub fn stop(config_path: &str, name: &str) -> i32 {
+    let Some(config) = read_config(config_path) else {
+        return 1;
+    };
+    let Some(service) = find_service(&config, name) else {
+        return not_found(name);
+    };
+    report("stop", service, svc::stop_service(&service.svc_filename))
+}
+
+/// Restarts the service found by its name in the config file
+/// the same way it is restarted after a deploy.
+///
+/// Returns exit code to be returned by the process.
+pub fn restart(config_path: &str, name: &str) -> i32 {
+    let Some(config) = read_config(config_path) else {
+        return 1;
+    };
+    let Some(service) = find_service(&config, name) else {
+        return not_found(name);
+    };
+    let service_info = ServiceInfo::new(service, &config.sys_svc_dir);
+    report("restart", service, restart_logic(&service_info))
+}
+
+/// Prints statuses of all services specified in the config
+/// file along with the commit deployed for each of them.
+/// Prints JSON instead of the table if `json` is set.
+///
+/// Returns exit code to be returned by the process.
+pub fn status(config_path: &str, json: bool) -> i32 {
+    let Some(config) = read_config(config_path) else {
+        return 1;
+    };
+    let now = Local::now().timestamp();
+    let statuses: Vec<ServiceStatus> = config
+        .services
+        .iter()
+        .map(|service| {
+            let unit = svc::service_status(&service.svc_filename).unwrap_or_default();
+            let deployment = record::read(&service.build_dir, &service.name);
+            ServiceStatus {
+                name: service.name.clone(),
+                unit: service.svc_filename.clone(),
+                active_state: fallback(unit.active_state),
+                sub_state: fallback(unit.sub_state),
+                main_pid: unit.main_pid,
+                uptime_secs: unit.active_since.map(|since| now - since),
+                commit: deployment.as_ref().map(|d| d.sha.clone()),
+                deployed_at: deployment.map(|d| d.deployed_at),
+            }
+        })
+        .collect();
+
+    if json {
+        match serde_json::to_string_pretty(&statuses) {
+            Ok(data) => println!("{}", data),
+            Err(e) => {
+                println!("Failed to serialise statuses: {}", e);
+                return 1;
+            }
+        }
+    } else {
+        print_table(&statuses);
+    }
+    0
+}
+
+/// Replaces unit files of all services with `svc_file_contents`
+/// from the config file. Diff of every changed file is printed
+/// and confirmation is asked unless `yes` is set. Previous unit
+/// files are kept as `.bak`, changed services are restarted after
+/// a single `daemon-reload`. A unit file that fails to be written
+/// does not stop the others from being replaced.
+///
+/// Returns exit code to be returned by the process.
+pub fn overwrite(config_path: &str, yes: bool) -> i32 {
+    let Some(config) = read_config(config_path) else {
+        return 1;
+    };
+    let svc_dir = &config.sys_svc_dir;
+
+    let mut changed = Vec::new();
+    for service in &config.services {
+        let old = match svc::read_unit(&service.svc_filename, svc_dir) {
+            Ok(old) => old.unwrap_or_default(),
+            Err(e) => {
+                println!("Failed to read {}: {}", service.svc_filename, e);
+                return 1;
+            }
+        };
+        let new = svc::unit_contents(&service.svc_file_contents);
+        if old != new {
+            print_diff(&service.svc_filename, &old, &new);
+            changed.push(service);
+        }
+    }
+
+    if changed.is_empty() {
+        println!("All unit files are up to date.");
+        return 0;
+    }
+    if !yes && !confirm("Apply the changes above?") {
+        println!("Nothing was changed.");
+        return 1;
+    }
+
+    let mut code = 0;
+    let mut written = Vec::new();
+    for service in changed {
+        let overwritten =
+            svc::overwrite_unit(&service.svc_filename, svc_dir, &service.svc_file_contents);
+        match overwritten {
+            Ok(()) => written.push(service),
+            Err(e) => {
+                println!("Failed to overwrite {}: {}", service.svc_filename, e);
+                code = 1;
+            }
+        }
+    }
+    if written.is_empty() {
+        return code;
+    }
+    match svc::daemon_reload() {
+        Ok(s) if s.success() => (),
+        Ok(s) => {
+            println!(
+                "`systemctl daemon-reload` exited with code {}.",
+                s.code().unwrap_or(1)
+            );
+            return s.code().unwrap_or(1);
+        }
+        Err(e) => {
+            println!("Failed to run `systemctl daemon-reload`: {}", e);
+            return 1;
+        }
+    }
+
+    for service in written {
+        let service_info = ServiceInfo::new(service, &config.sys_svc_dir);
+        let restarted = report("restart", service, restart_logic(&service_info));
+        if restarted != 0 {
+            code = restarted;
+        }
+    }
+    code
+}
+
+fn print_diff(svc_filename: &str, old: &str, new: &str) {
+    let diff = TextDiff::from_lines(old, new);
+    let old_name = format!("a/{}", svc_filename);
+    let new_name = format!("b/{}", svc_filename);
+    print!("{}", diff.unified_diff().header(&old_name, &new_name));
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    _ = io::stdout().flush();
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn fallback(state: String) -> String {
+    if state.is_empty() {
+        String::from("unknown")
+    } else {
+        state
+    }
+}
+
+fn print_table(statuses: &[ServiceStatus]) {
+    let header = [
+        "NAME", "UNIT", "STATE", "PID", "UPTIME", "COMMIT", "DEPLOYED",
+    ];
+    let rows: Vec<[String; 7]> = statuses
+        .iter()
+        .map(|s| {
+            [
+                s.name.clone(),
+                s.unit.clone(),
+                format!("{} ({})", s.active_state, s.sub_state),
+                s.main_pid.map_or(String::from("-"), |pid| pid.to_string()),
+                s.uptime_secs.map_or(String::from("-"), fmt_uptime),
+                s.commit
+                    .as_ref()
+                    .map_or(String::from("-"), |sha| sha.chars().take(7).collect()),
+                s.deployed_at.clone().unwrap_or(String::from("-")),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let print_row = |cells: &[&str]| {
+        let line: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(&header);
+    for row in &rows {
+        print_row(&row.each_ref().map(String::as_str));
+    }
+}
+
+/// Formats seconds as `1d 2h 3m`, `2h 3m` or `3m 4s`.
+fn fmt_uptime(secs: i64) -> String {
+    let (days, hours) = (secs / 86400, secs % 86400 / 3600);
+    let (minutes, seconds) = (secs % 3600 / 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}h {}m", days, hours, minutes)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else {
+        format!("{}m {}s", minutes, seconds)
+    }
+}
+
+/// Reads the config file, printing the error if it fails.
+fn read_config(config_path: &str) -> Option<ConfigFile> {
+    match deserialise(config_path) {
+        Ok(config) => Some(config),
+        Err(e) => {
+            println!("{}", e);
+            None
+        }
+    }
+}
+
+fn find_service<'a>(config: &'a ConfigFile, name: &str) -> Option<&'a Service> {
+    config.services.iter().find(|s| s.name == name)
+}
+
+fn not_found(name: &str) -> i32 {
+    println!("Service \"{}\" is not specified in the config file.", name);
+    1
+}
+
+/// Prints outcome of the service manager's action
+/// and converts it into exit code.
+fn report(action: &str, service: &Service, status: Result<ExitStatus>) -> i32 {
+    match status {
+        Ok(s) if s.success() => {
+            println!(
+                "Service {} ({}): {} succeeded.",
+                service.name, service.svc_filename, action
+            );
+            0
+        }
+        Ok(s) => {
+            let code = s.code().unwrap_or(1);
+            println!(
+                "Failed to {} service {} ({}), exit code {}.",
+                action, service.name, service.svc_filename, code
+            );
+            code
+        }
+        Err(e) => {
+            println!(
+                "Failed to {} service {} ({}): {}",
+                action, service.name, service.svc_filename, e
+            );
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fmt_uptime() {
+        assert_eq!(fmt_uptime(42), "0m 42s");
+        assert_eq!(fmt_uptime(3 * 3600 + 5 * 60), "3h 5m");
+        assert_eq!(fmt_uptime(2 * 86400 + 3600 + 60), "2d 1h 1m");
+    }
+}
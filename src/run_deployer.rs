@@ -1,143 +1,455 @@
-use crate::generate_conf::file_struct::ConfigFile;
-use serde_json;
-use std::{fs::File, io::Read, path::Path};
+use crate::generate_conf::file_struct::{ConfigFile, Provider, Secret};
+use crate::generate_conf::jsonc;
+use crate::log;
+use chrono::{DateTime, Local};
+use std::collections::HashSet;
+use std::net::ToSocketAddrs;
+use std::{env, fs, fs::File, io::Read, path::Path, process};
 
-mod pull;
+pub mod config_error;
+pub mod control;
+pub mod paths;
+pub mod provider;
+pub mod pull;
+#[cfg(test)]
+mod stub;
+pub mod webhook;
 
+use config_error::ConfigError;
+use provider::{credentials::Credentials, github_app};
 use pull::{ping, RepositoryInfo};
 
 /// Function that starts Deployer. It makes
-/// request to GitHub's REST API every 60 seconds.
+/// request to GitHub's REST API every 60 seconds
+/// (every 10 minutes if it listens for webhooks).
 /// As an argument it takes path to the config file.
 ///
-/// Panics if directory does not exist, no services
-/// specified, token/repository/branch is not specified or
-/// repository link is invalid.
+/// Exits with all the problems found printed if the
+/// config file cannot be read or is invalid. Once running,
+/// only fatal misconfiguration makes it exit, other
+/// failures are logged and retried.
 pub async fn run(path: &str) {
-    let config = deserialise(path);
-    if config.token.is_empty() || config.token == "YOUR-GITHUB-TOKEN-HERE" {
-        panic!("Github token is not specified!");
+    let (config, repository) = match load(path) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            println!("{}", e);
+            process::exit(1);
+        }
+    };
+    let reloads = control::listen(path);
+    let webhooks = webhook::listen(&config);
+    if let Err(e) = ping(path, config, repository, reloads, webhooks).await {
+        log!("{}, Deployer cannot continue.", e);
+        process::exit(1);
+    }
+}
+
+/// Environment variable the token is read from
+/// if the config file does not specify it.
+const TOKEN_ENV: &str = "DEPLOYER_TOKEN";
+
+/// Reads the config file and runs all the checks on it.
+/// Used both on start and on every reload.
+///
+/// `token` of the returned config holds the resolved token.
+pub fn load(path: &str) -> Result<(ConfigFile, RepositoryInfo), ConfigError> {
+    let mut config = deserialise(path)?;
+    let repository = validate(&config)?;
+    config.token = resolve_token(&config).ok();
+    Ok((config, repository))
+}
+
+/// Finds the token in `token` (either the value itself or
+/// `${VAR}` reference), in `token_file` or in `DEPLOYER_TOKEN`
+/// environment variable, in this order.
+fn resolve_token(config: &ConfigFile) -> Result<Secret, String> {
+    let token = config.token.as_ref().map(Secret::expose);
+    let token = match (token, &config.token_file) {
+        (Some(_), Some(_)) => {
+            return Err(String::from(
+                "`token` and `token_file` cannot be used together.",
+            ))
+        }
+        (Some(token), None) => match token.strip_prefix("${").and_then(|t| t.strip_suffix('}')) {
+            Some(var) => {
+                env::var(var).map_err(|_| format!("environment variable {} is not set.", var))?
+            }
+            None => token.to_owned(),
+        },
+        (None, Some(file)) => fs::read_to_string(file)
+            .map_err(|e| format!("failed to read token file \"{}\": {}", file.display(), e))?
+            .trim()
+            .to_owned(),
+        (None, None) => env::var(TOKEN_ENV).unwrap_or_default(),
+    };
+
+    if token.is_empty() || token == "YOUR-GITHUB-TOKEN-HERE" {
+        return Err(String::from("token is not specified."));
+    }
+    Ok(Secret::new(token))
+}
+
+/// Checks all values of the config file and returns
+/// every problem found, not only the first one.
+pub fn validate(config: &ConfigFile) -> Result<RepositoryInfo, ConfigError> {
+    let mut problems = Vec::new();
+
+    // Plain git remotes may not need any credentials
+    let token_optional =
+        config.provider == Provider::Git && config.token.is_none() && config.token_file.is_none();
+    if let Some(app) = &config.github_app {
+        if config.provider != Provider::GitHub {
+            problems.push(String::from(
+                "`github_app`: can only be used with \"github\" provider.",
+            ));
+        }
+        if config.token.is_some() || config.token_file.is_some() {
+            problems.push(String::from(
+                "`github_app`: cannot be used together with `token` or `token_file`.",
+            ));
+        }
+        if let Err(e) = github_app::read_key(&app.private_key) {
+            problems.push(format!("`github_app`: {}", e));
+        }
+    } else if let Err(e) = resolve_token(config) {
+        if !token_optional {
+            problems.push(format!("`token`: {}", e));
+        }
     }
+
+    let mut repository = None;
     if config.repository.is_empty()
         || config.repository == "https://github.com/your-repository/link"
     {
-        panic!("Github repository is not specified!");
+        problems.push(String::from("`repository`: repository is not specified."));
+    } else {
+        let api_url = config.api_url.as_deref();
+        let (url, branch) = (&config.repository, &config.branch);
+        match provider::repository_info(config.provider, url, branch, api_url) {
+            Ok(info) => repository = Some(info),
+            Err(e) => problems.push(format!("`repository`: {}", e)),
+        }
     }
-    let repository = url_fmt(&config.repository, &config.branch);
 
-    // NOTE: Only global directories are valid yet
-    validate_dir(&config.pull_dir);
-    if config.services.len() == 0 {
-        panic!("Not a single service specified :<");
+    match &config.ssh_key {
+        Some(key) if !key.is_file() => {
+            problems.push(format!("`ssh_key`: \"{}\" does not exist.", key.display()))
+        }
+        None if is_ssh_url(&config.repository) => problems.push(String::from(
+            "`ssh_key`: required to clone the repository over SSH.",
+        )),
+        _ => (),
+    }
+    if is_ssh_url(&config.repository) {
+        let known_hosts = Credentials::from_config(config, &config.repository).known_hosts;
+        if !known_hosts.is_file() {
+            problems.push(format!(
+                "`known_hosts`: \"{}\" does not exist.",
+                known_hosts.display()
+            ));
+        }
     }
 
-    ping(&config, &repository).await.unwrap();
-}
-
-/// Formats URL from `github.com/author/their-repo` to
-/// `https://api.github.com/repos/author/their-repo/commits`.
-/// Panics if URL is badly formatted.
-fn url_fmt<'a>(url: &'a str, branch: &'a str) -> RepositoryInfo<'a> {
-    const INVALID_URL: &str = "Invalid repository URL!";
-
-    let list: Vec<&str> = if url.starts_with("https://") {
-        url[8..].split('/').collect()
-    } else {
-        url.split('/').collect()
-    };
+    if let Some(webhook) = &config.webhook {
+        if webhook.address.to_socket_addrs().is_err() {
+            problems.push(format!(
+                "`webhook`: \"{}\" is not a valid address to listen on.",
+                webhook.address
+            ));
+        }
+        if webhook.secret.expose().is_empty() {
+            problems.push(String::from("`webhook`: secret is not specified."));
+        }
+        if config.provider == Provider::GitLab {
+            problems.push(String::from(
+                "`webhook`: GitLab webhooks are not supported.",
+            ));
+        }
+    }
 
-    if list.len() != 3 {
-        panic!("{}", INVALID_URL);
+    if config.poll_interval == Some(0) {
+        problems.push(String::from("`poll_interval`: must be at least 1 second."));
+    }
+    if let Some(keep) = &config.keep_pulls {
+        if keep.count.is_none() && keep.max_age_days.is_none() {
+            problems.push(String::from(
+                "`keep_pulls`: set `count`, `max_age_days` or both.",
+            ));
+        }
+        if keep.count == Some(0) {
+            problems.push(String::from(
+                "`keep_pulls.count`: must be at least 1, the newest pull is always kept.",
+            ));
+        }
     }
 
-    let domain = list[0];
-    let author = list[1];
-    let repository = list[2];
+    if let Err(e) = validate_dir(&config.pull_dir) {
+        problems.push(format!("`pull_dir`: {}", e));
+    }
+    if let Err(e) = validate_dir(&config.sys_svc_dir) {
+        problems.push(format!("`sys_svc_dir`: {}", e));
+    }
 
-    if domain != "github.com" {
-        panic!("Invalid repository domain!");
+    if config.services.is_empty() {
+        problems.push(String::from(
+            "`services`: not a single service specified :<",
+        ));
+    }
+    let mut names = HashSet::new();
+    for service in &config.services {
+        if !names.insert(&service.name) {
+            problems.push(format!(
+                "`services`: name \"{}\" is used more than once.",
+                service.name
+            ));
+        }
     }
 
-    if author.is_empty() || repository.is_empty() {
-        panic!("{}", INVALID_URL);
+    if let Some(repository) = &repository {
+        if config.shallow_clone && is_local_url(&repository.clone_url) {
+            problems.push(String::from(
+                "`shallow_clone`: git does not support it for local repositories.",
+            ));
+        }
     }
+    problems.extend(validate_sparse_checkout(config));
 
-    if branch.is_empty() {
-        panic!("No main branch specified!");
+    match repository {
+        Some(repository) if problems.is_empty() => Ok(repository),
+        _ => Err(ConfigError::Invalid(problems)),
     }
+}
+
+/// Whether git is going to connect to `url` over SSH.
+fn is_ssh_url(url: &str) -> bool {
+    url.starts_with("ssh://") || provider::scp_address(url).is_some()
+}
 
-    let url = format!(
-        "https://api.github.com/repos/{}/{}/commits/{}",
-        author, repository, branch
-    );
+/// Whether `url` is a repository on this machine
+/// (`file://` or a path) rather than a remote one.
+fn is_local_url(url: &str) -> bool {
+    url.starts_with("file://") || !(url.contains("://") || is_ssh_url(url))
+}
 
-    RepositoryInfo {
-        url,
-        author,
-        name: repository,
+/// Problems with `sparse_checkout` and `shared_paths`.
+fn validate_sparse_checkout(config: &ConfigFile) -> Vec<String> {
+    let mut problems = Vec::new();
+    if config.sparse_checkout {
+        for service in config.services.iter().filter(|s| s.custom_dir.is_none()) {
+            problems.push(format!(
+                "`sparse_checkout`: service \"{}\" has no `custom_dir`, it needs the whole repository.",
+                service.name
+            ));
+        }
+    } else if !config.shared_paths.is_empty() {
+        problems.push(String::from(
+            "`shared_paths`: only used with `sparse_checkout`.",
+        ));
+    }
+    for path in &config.shared_paths {
+        let relative = Path::new(path)
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)));
+        if path.is_empty() || !relative {
+            problems.push(format!(
+                "`shared_paths`: \"{}\" is not a path within the repository.",
+                path
+            ));
+        }
     }
+    problems
 }
 
 /// Check if specified directory exists.
-/// Panics if it does not.
-fn validate_dir(dir: &str) {
-    let path = Path::new(dir);
-    if !path.exists() {
-        panic!("Path \"{}\" does not exist!", dir);
+fn validate_dir(dir: &Path) -> Result<(), String> {
+    if !dir.exists() {
+        return Err(format!("\"{}\" does not exist.", dir.display()));
     }
+    Ok(())
 }
 
-/// Converts JSON data from the config file into
-/// `ConfigFile` struct. Panics if fails to
-/// either open the config file or to read it.
-fn deserialise(path: &str) -> ConfigFile {
-    let mut buf = Vec::new();
-    let mut file = File::open(path).expect("Failed to open the config file");
-    file.read_to_end(&mut buf)
-        .expect("Failed to read the config file");
+/// Converts JSONC data from the config file into
+/// `ConfigFile` struct. Fails if the config file cannot
+/// be read or parsed, does not check its values.
+///
+/// All the directories are made absolute, relative
+/// ones are resolved against the config file's directory.
+pub fn deserialise(path: &str) -> Result<ConfigFile, ConfigError> {
+    let mut buf = String::new();
+    let mut file = File::open(path)?;
+    file.read_to_string(&mut buf)?;
 
-    serde_json::from_slice(&buf).expect("Failed to parse json config")
+    let mut config: ConfigFile = serde_json::from_str(&jsonc::strip(&buf))?;
+    let base = paths::config_dir(path);
+    config.pull_dir = paths::resolve(&config.pull_dir, &base);
+    config.sys_svc_dir = paths::resolve(&config.sys_svc_dir, &base);
+    for service in &mut config.services {
+        service.build_dir = paths::resolve(&service.build_dir, &base);
+    }
+    if let Some(app) = &mut config.github_app {
+        app.private_key = paths::resolve(&app.private_key, &base);
+    }
+    config.token_file = config.token_file.map(|file| paths::resolve(&file, &base));
+    config.ssh_key = config.ssh_key.map(|key| paths::resolve(&key, &base));
+    config.known_hosts = config.known_hosts.map(|file| paths::resolve(&file, &base));
+    Ok(config)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_is_ssh_url() {
+        assert!(is_ssh_url("git@github.com:Makefolder/deployer.git"));
+        assert!(is_ssh_url(
+            "ssh://git@git.example.com:2222/team/project.git"
+        ));
+        assert!(!is_ssh_url("https://user@github.com/Makefolder/deployer"));
+    }
+
+    fn valid_config() -> ConfigFile {
+        ConfigFile {
+            token: Some(Secret::new(String::from("token"))),
+            repository: String::from("github.com/Makefolder/deployer"),
+            pull_dir: PathBuf::from("/"),
+            sys_svc_dir: PathBuf::from("/"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_valid_config() {
+        assert!(validate(&valid_config()).is_ok());
+    }
 
-    // Test URL formatter
     #[test]
-    fn test_valid_url_fmt() {
-        let url = "github.com/Makefolder/deployer";
-        let branch = "master";
-        let repository_info = url_fmt(url, branch);
-        assert_eq!(
-            repository_info.url,
-            "https://api.github.com/repos/Makefolder/deployer/commits/master"
-        );
-        assert_eq!(repository_info.author, "Makefolder");
-        assert_eq!(repository_info.name, "deployer");
+    fn test_all_problems_are_reported() {
+        let mut config = valid_config();
+        config.token = Some(Secret::new(String::from("YOUR-GITHUB-TOKEN-HERE")));
+        config.pull_dir = PathBuf::from("/does/not/exist");
+        config.services.push(config.services[0].clone());
+
+        let Err(ConfigError::Invalid(problems)) = validate(&config) else {
+            panic!("config should be invalid");
+        };
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("`token`"));
+        assert!(problems[1].starts_with("`pull_dir`"));
+        assert!(problems[2].contains("used more than once"));
+    }
+
+    #[test]
+    fn test_github_app_replaces_token() {
+        let dir = stub::TempDir::new("app-config");
+        let mut config = valid_config();
+        config.github_app = Some(stub::test_app(&dir));
+        let Err(ConfigError::Invalid(problems)) = validate(&config) else {
+            panic!("config should be invalid");
+        };
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].contains("cannot be used together"));
+
+        config.token = None;
+        assert!(validate(&config).is_ok());
+    }
+
+    #[test]
+    fn test_ssh_url_requires_key() {
+        let mut config = valid_config();
+        config.repository = String::from("git@github.com:Makefolder/deployer.git");
+        config.known_hosts = Some(PathBuf::from("/does/not/exist"));
+        let Err(ConfigError::Invalid(problems)) = validate(&config) else {
+            panic!("config should be invalid");
+        };
+        assert_eq!(problems.len(), 2);
+        assert!(problems[0].starts_with("`ssh_key`"));
+        assert!(problems[1].starts_with("`known_hosts`"));
+    }
+
+    #[test]
+    fn test_sparse_checkout_problems() {
+        let mut config = valid_config();
+        config.provider = Provider::Git;
+        config.repository = String::from("file:///srv/git/app.git");
+        config.shallow_clone = true;
+        config.sparse_checkout = true;
+        config.shared_paths = vec![String::from("../outside")];
+        let Err(ConfigError::Invalid(problems)) = validate(&config) else {
+            panic!("config should be invalid");
+        };
+        assert_eq!(problems.len(), 3);
+        assert!(problems[0].starts_with("`shallow_clone`"));
+        // The service has no `custom_dir`
+        assert!(problems[1].starts_with("`sparse_checkout`"));
+        assert!(problems[2].starts_with("`shared_paths`"));
+    }
+
+    #[test]
+    fn test_parse_error_position() {
+        let err: ConfigError = serde_json::from_str::<ConfigFile>("{\n  \"branch\": 1\n}")
+            .unwrap_err()
+            .into();
+        let ConfigError::Parse { line, column, .. } = err else {
+            panic!("should be a parse error");
+        };
+        assert_eq!((line, column), (2, 13));
+    }
+
+    #[test]
+    fn test_token_from_env_reference() {
+        env::set_var("DEPLOYER_TEST_TOKEN_REF", "from-env");
+        let config = ConfigFile {
+            token: Some(Secret::new(String::from("${DEPLOYER_TEST_TOKEN_REF}"))),
+            ..valid_config()
+        };
+        assert_eq!(resolve_token(&config).unwrap().expose(), "from-env");
+
+        let config = ConfigFile {
+            token: Some(Secret::new(String::from("${DEPLOYER_TEST_TOKEN_UNSET}"))),
+            ..valid_config()
+        };
+        assert!(resolve_token(&config).is_err());
     }
 
     #[test]
-    #[should_panic(expected = "Invalid repository domain!")]
-    fn test_invalid_domain_url_fmt() {
-        let url = "gitlab.com/Makefolder/deployer";
-        let branch = "master";
-        url_fmt(url, branch);
+    fn test_token_from_file() {
+        let dir = stub::TempDir::new("token");
+        let file = dir.join("token");
+        fs::write(&file, "from-file\n").unwrap();
+        let config = ConfigFile {
+            token: None,
+            token_file: Some(file),
+            ..valid_config()
+        };
+        assert_eq!(resolve_token(&config).unwrap().expose(), "from-file");
     }
 
     #[test]
-    #[should_panic(expected = "Invalid repository URL!")]
-    fn test_invalid_author_url_fmt() {
-        let url = "github.com//deployer";
-        let branch = "master";
-        url_fmt(url, branch);
+    fn test_token_file_is_relative_to_config() {
+        let dir = stub::TempDir::new("token-dir");
+        let config_path = dir.join("deployer-config.jsonc");
+        let data = r#"{
+            "repository": "github.com/Makefolder/deployer",
+            "branch": "main",
+            "token_file": "secrets/token",
+            "pull_dir": ".",
+            "sys_svc_dir": ".",
+            "services": []
+        }"#;
+        fs::write(&config_path, data).unwrap();
+        let config = deserialise(config_path.to_str().unwrap()).unwrap();
+        assert_eq!(config.token_file, Some(dir.join("secrets/token")));
     }
 
     #[test]
-    #[should_panic(expected = "Invalid repository URL!")]
-    fn test_invalid_name_url_fmt() {
-        let url = "github.com/Makefolder/";
-        let branch = "master";
-        url_fmt(url, branch);
+    fn test_token_is_not_printed() {
+        let config = ConfigFile {
+            token: Some(Secret::new(String::from("ghp_very_secret"))),
+            ..valid_config()
+        };
+        assert!(!format!("{:?}", config).contains("ghp_very_secret"));
+        assert_eq!(config.token.unwrap().to_string(), "***");
     }
 }
@@ -0,0 +1,65 @@
+use std::{error::Error, fmt::Display, io};
+
+/// Everything that can be wrong with the config file.
+/// Invalid values are collected all at once, so they
+/// can be fixed in one go.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// Failed to open or read the file.
+    Read(io::Error),
+    /// The file is not valid JSONC or does not match `ConfigFile`.
+    Parse {
+        line: usize,
+        column: usize,
+        message: String,
+    },
+    /// The file was parsed but some of its values are invalid.
+    Invalid(Vec<String>),
+}
+
+impl Error for ConfigError {}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Read(e) => write!(f, "Failed to read the config file: {e}"),
+            Self::Parse {
+                line,
+                column,
+                message,
+            } => write!(
+                f,
+                "Failed to parse the config file at line {line}, column {column}: {message}"
+            ),
+            Self::Invalid(problems) => {
+                write!(f, "The config file is invalid:")?;
+                for problem in problems {
+                    write!(f, "\n  - {problem}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        Self::Read(e)
+    }
+}
+
+impl From<serde_json::Error> for ConfigError {
+    fn from(e: serde_json::Error) -> Self {
+        // Position is reported separately, drop it from the message
+        let message = e.to_string();
+        let message = match message.rsplit_once(" at line ") {
+            Some((message, _)) => message.to_owned(),
+            None => message,
+        };
+        Self::Parse {
+            line: e.line(),
+            column: e.column(),
+            message,
+        }
+    }
+}
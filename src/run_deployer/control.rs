@@ -0,0 +1,178 @@
+// Control channel of the running Deployer.
+// Deployer listens on a Unix socket next to its config
+// file (and for SIGHUP) so it can be asked to reload
+// the config without being restarted.
+
+use crate::log;
+use chrono::{DateTime, Local};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, oneshot};
+
+const RELOAD: &str = "reload";
+
+/// Request to reload the config file. The outcome is sent
+/// back through `reply` if whoever asked is waiting for it.
+pub struct ReloadRequest {
+    pub reply: Option<oneshot::Sender<Result<(), String>>>,
+}
+
+/// Path of the control socket of Deployer
+/// started with `config_path`.
+pub fn socket_path(config_path: &str) -> PathBuf {
+    let dir = Path::new(config_path)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    dir.join(".deployer.sock")
+}
+
+/// Starts listening for reload requests on the control
+/// socket and for SIGHUP. Failing to set up either of
+/// them is logged but does not stop Deployer.
+pub fn listen(config_path: &str) -> mpsc::Receiver<ReloadRequest> {
+    let (tx, rx) = mpsc::channel(4);
+
+    let path = socket_path(config_path);
+    // Socket left behind by a previous run
+    _ = std::fs::remove_file(&path);
+    match UnixListener::bind(&path) {
+        Ok(listener) => {
+            log!("Listening for commands on {}", path.display());
+            tokio::spawn(accept(listener, tx.clone()));
+        }
+        Err(e) => {
+            log!("Failed to bind control socket {}: {}", path.display(), e);
+        }
+    }
+
+    match signal(SignalKind::hangup()) {
+        Ok(mut hangup) => {
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    log!("Received SIGHUP.");
+                    if tx.send(ReloadRequest { reply: None }).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        Err(e) => {
+            log!("Failed to listen for SIGHUP: {}", e);
+        }
+    }
+    rx
+}
+
+async fn accept(listener: UnixListener, tx: mpsc::Sender<ReloadRequest>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle(stream, tx).await {
+                log!("Control connection failed: {}", e);
+            }
+        });
+    }
+}
+
+async fn handle(stream: UnixStream, tx: mpsc::Sender<ReloadRequest>) -> std::io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut line = String::new();
+    BufReader::new(read).read_line(&mut line).await?;
+
+    let answer = if line.trim() == RELOAD {
+        let (reply, outcome) = oneshot::channel();
+        _ = tx.send(ReloadRequest { reply: Some(reply) }).await;
+        match outcome.await {
+            Ok(Ok(())) => String::from("ok"),
+            Ok(Err(e)) => format!("error: {}", e),
+            Err(_) => String::from("error: Deployer is shutting down"),
+        }
+    } else {
+        format!("error: unknown command \"{}\"", line.trim())
+    };
+    write.write_all(answer.as_bytes()).await?;
+    write.write_all(b"\n").await
+}
+
+/// Asks Deployer started with `config_path` to reload its config.
+///
+/// Returns exit code to be returned by the process.
+pub async fn reload(config_path: &str) -> i32 {
+    let path = socket_path(config_path);
+    let mut stream = match UnixStream::connect(&path).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            println!(
+                "Failed to connect to Deployer at {} (is it running?): {}",
+                path.display(),
+                e
+            );
+            return 1;
+        }
+    };
+
+    let mut answer = String::new();
+    let sent = async {
+        stream.write_all(format!("{}\n", RELOAD).as_bytes()).await?;
+        BufReader::new(&mut stream).read_line(&mut answer).await
+    };
+    if let Err(e) = sent.await {
+        println!("Failed to talk to Deployer: {}", e);
+        return 1;
+    }
+
+    match answer.trim() {
+        "ok" => {
+            println!("Configuration reloaded.");
+            0
+        }
+        answer => {
+            let reason = answer.strip_prefix("error: ").unwrap_or(answer);
+            println!("Failed to reload configuration: {}", reason);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_deployer::stub;
+
+    #[tokio::test]
+    async fn test_reload_round_trip() {
+        let dir = stub::TempDir::new("control");
+        let config_path = dir.join("deployer-config.jsonc");
+        let config_path = config_path.to_str().unwrap();
+
+        let mut reloads = listen(config_path);
+        let daemon = tokio::spawn(async move {
+            let first = reloads.recv().await.unwrap();
+            _ = first.reply.unwrap().send(Ok(()));
+            let second = reloads.recv().await.unwrap();
+            _ = second.reply.unwrap().send(Err(String::from("bad config")));
+        });
+
+        assert_eq!(reload(config_path).await, 0);
+        assert_eq!(reload(config_path).await, 1);
+        daemon.await.unwrap();
+    }
+
+    #[test]
+    fn test_socket_path() {
+        assert_eq!(
+            socket_path("/etc/deployer/deployer-config.jsonc"),
+            PathBuf::from("/etc/deployer/.deployer.sock")
+        );
+        assert_eq!(
+            socket_path("deployer-config.jsonc"),
+            PathBuf::from("./.deployer.sock")
+        );
+    }
+}
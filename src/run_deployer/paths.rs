@@ -0,0 +1,96 @@
+// Paths in the config file may be relative to the
+// config file's directory and may start with `~` or
+// `$HOME`. They are turned into absolute paths once,
+// when the config file is loaded.
+
+use std::{
+    env,
+    path::{Component, Path, PathBuf},
+};
+
+/// Expands `~` and `$HOME` at the start of `path` and makes
+/// it absolute, relative paths are resolved against `base`.
+/// `.` and `..` are resolved without touching the file system,
+/// so the path does not have to exist.
+pub fn resolve(path: &Path, base: &Path) -> PathBuf {
+    let path = expand_home(path);
+    let path = if path.is_absolute() {
+        path
+    } else {
+        base.join(path)
+    };
+
+    let mut normalised = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalised.pop();
+            }
+            c => normalised.push(c),
+        }
+    }
+    normalised
+}
+
+/// Directory of the config file as an absolute path,
+/// the base for relative paths within it.
+pub fn config_dir(config_path: &str) -> PathBuf {
+    let dir = Path::new(config_path).parent().unwrap_or(Path::new(""));
+    let cwd = env::current_dir().unwrap_or_default();
+    resolve(dir, &cwd)
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    let Some(home) = env::var_os("HOME") else {
+        return path.to_path_buf();
+    };
+    let mut components = path.components();
+    match components.next() {
+        Some(Component::Normal(first))
+            if first == "~" || first == "$HOME" || first == "${HOME}" =>
+        {
+            Path::new(&home).join(components.as_path())
+        }
+        _ => path.to_path_buf(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_relative() {
+        let base = Path::new("/etc/deployer");
+        assert_eq!(
+            resolve(Path::new("pulls"), base),
+            PathBuf::from("/etc/deployer/pulls")
+        );
+        assert_eq!(
+            resolve(Path::new("./a/../b"), base),
+            PathBuf::from("/etc/deployer/b")
+        );
+        assert_eq!(
+            resolve(Path::new("../var"), base),
+            PathBuf::from("/etc/var")
+        );
+        assert_eq!(
+            resolve(Path::new("/var/www"), base),
+            PathBuf::from("/var/www")
+        );
+    }
+
+    #[test]
+    fn test_resolve_home() {
+        let home = PathBuf::from(env::var_os("HOME").unwrap());
+        let base = Path::new("/etc/deployer");
+        assert_eq!(resolve(Path::new("~"), base), home);
+        assert_eq!(resolve(Path::new("~/pulls"), base), home.join("pulls"));
+        assert_eq!(resolve(Path::new("$HOME/pulls"), base), home.join("pulls"));
+        assert_eq!(
+            resolve(Path::new("~user/pulls"), base),
+            base.join("~user/pulls")
+        );
+    }
+}
@@ -0,0 +1,444 @@
+// Services hosting the repository (GitHub, GitLab, Gitea/Forgejo
+// and plain git remotes). Each of them implements `SourceProvider`:
+// how to ask for the latest commit of a branch and how to
+// authenticate when cloning. The rest of the deployer only
+// talks to the trait, `from_config` picks the implementation.
+
+use crate::debug;
+use crate::generate_conf::file_struct::{ConfigFile, Provider};
+use crate::run_deployer::pull::RepositoryInfo;
+use chrono::{DateTime, Local, Utc};
+use credentials::Credentials;
+use git2::RemoteCallbacks;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use std::error::Error;
+use std::fmt::Display;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+
+pub mod credentials;
+mod git;
+mod gitea;
+mod github;
+pub mod github_app;
+mod gitlab;
+#[cfg(test)]
+pub mod mock;
+
+/// Latest commit of the branch. `message` and `author`
+/// are only known if the provider returns them.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Commit {
+    pub sha: String,
+    pub message: Option<String>,
+    pub author: Option<String>,
+}
+
+impl Display for Commit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.sha)?;
+        if let Some(message) = &self.message {
+            write!(f, " \"{}\"", message.lines().next().unwrap_or_default())?;
+        }
+        if let Some(author) = &self.author {
+            write!(f, " by {}", author)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum HeadError {
+    /// The API replied with unsuccessful status.
+    Status(StatusCode),
+    Request(reqwest::Error),
+    Parse(serde_json::Error),
+    Git(git2::Error),
+    /// Failed to get a token to authenticate with.
+    Auth(String),
+    /// The API must not be asked until this time passes.
+    RateLimited(Duration),
+}
+
+impl Error for HeadError {}
+
+impl Display for HeadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Status(status) => write!(f, "Failed to fetch data: {}", status),
+            Self::Request(e) => write!(f, "Request failed: {}", e),
+            Self::Parse(e) => write!(f, "Failed to parse the response: {}", e),
+            Self::Git(e) => write!(f, "Failed to query the remote: {}", e),
+            Self::Auth(e) => write!(f, "Failed to authenticate: {}", e),
+            Self::RateLimited(wait) => {
+                write!(f, "Rate limit exhausted for {} seconds", wait.as_secs())
+            }
+        }
+    }
+}
+
+impl From<reqwest::Error> for HeadError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Request(e)
+    }
+}
+
+impl From<serde_json::Error> for HeadError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<git2::Error> for HeadError {
+    fn from(e: git2::Error) -> Self {
+        Self::Git(e)
+    }
+}
+
+pub type HeadFuture<'a> = Pin<Box<dyn Future<Output = Result<Commit, HeadError>> + Send + 'a>>;
+pub type PrepareFuture<'a> = Pin<Box<dyn Future<Output = Result<(), HeadError>> + Send + 'a>>;
+
+/// Everything the deployer needs to know about
+/// the service hosting the repository.
+pub trait SourceProvider: Send + Sync {
+    /// Asks for the commit the configured branch points to.
+    fn head<'a>(&'a self, client: &'a Client) -> HeadFuture<'a>;
+
+    /// URL the repository is cloned from.
+    fn clone_url(&self) -> &str;
+
+    /// Gets what `callbacks` need ready, called before every clone.
+    /// Nothing to do unless credentials expire, as tokens of
+    /// GitHub Apps do.
+    fn prepare<'a>(&'a self, _client: &'a Client) -> PrepareFuture<'a> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Callbacks git2 asks for credentials and verifies
+    /// the remote at `url` with.
+    fn callbacks(&self, url: &str) -> RemoteCallbacks<'_>;
+}
+
+/// Implementation of `SourceProvider` selected by `provider`
+/// in the config file.
+pub fn from_config(config: &ConfigFile, repository: &RepositoryInfo) -> Box<dyn SourceProvider> {
+    let credentials = Credentials::from_config(config, &repository.clone_url);
+    match repository.provider {
+        Provider::GitHub => {
+            let api_url = github::api_url(config.api_url.as_deref());
+            let app = config
+                .github_app
+                .as_ref()
+                .map(|app| github_app::App::new(app, api_url));
+            Box::new(github::GitHub::new(repository, credentials, app))
+        }
+        Provider::GitLab => Box::new(gitlab::GitLab::new(repository, credentials)),
+        Provider::Gitea => Box::new(gitea::Gitea::new(repository, credentials)),
+        Provider::Git => Box::new(git::Git::new(repository, credentials)),
+    }
+}
+
+/// Checks `repository` from the config and works out where
+/// the provider is asked for new commits and where it is
+/// cloned from. `api_url` overrides the provider's default API.
+pub fn repository_info(
+    provider: Provider,
+    url: &str,
+    branch: &str,
+    api_url: Option<&str>,
+) -> Result<RepositoryInfo, String> {
+    if branch.is_empty() {
+        return Err(String::from("No main branch specified!"));
+    }
+    match provider {
+        Provider::GitHub => github::repository_info(url, branch, api_url),
+        Provider::GitLab => gitlab::repository_info(url, branch, api_url),
+        Provider::Gitea => gitea::repository_info(url, branch, api_url),
+        Provider::Git => Ok(git::repository_info(url, branch)),
+    }
+}
+
+const INVALID_URL: &str = "Invalid repository URL!";
+
+/// Repository on a forge as written in the config: `host/owner/name`,
+/// optionally with `https://` and `.git`, or `git@host:owner/name.git`
+/// to clone it over SSH.
+struct ForgeUrl {
+    host: String,
+    /// Everything between the host and the name,
+    /// (sub)groups included.
+    owner: Vec<String>,
+    name: String,
+    clone_url: String,
+}
+
+impl ForgeUrl {
+    fn parse(url: &str) -> Result<Self, String> {
+        // `git@host:owner/repo.git` is cloned over SSH as is
+        let ssh_address = scp_address(url);
+        let address = match &ssh_address {
+            Some(address) => address.as_str(),
+            None => url.strip_prefix("https://").unwrap_or(url),
+        };
+        let address = address.strip_suffix(".git").unwrap_or(address);
+        let list: Vec<&str> = address.split('/').collect();
+        if list.len() < 3 || list.iter().any(|s| s.is_empty()) {
+            return Err(String::from(INVALID_URL));
+        }
+
+        let host = list[0].to_owned();
+        let owner: Vec<String> = list[1..list.len() - 1]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let name = list[list.len() - 1].to_owned();
+        let clone_url = match ssh_address {
+            Some(_) => url.to_owned(),
+            None => format!("https://{}/{}/{}.git", host, owner.join("/"), name),
+        };
+        Ok(ForgeUrl {
+            host,
+            owner,
+            name,
+            clone_url,
+        })
+    }
+
+    /// Fails unless the owner is a single user or organisation.
+    fn single_owner(self) -> Result<Self, String> {
+        match self.owner.len() {
+            1 => Ok(self),
+            _ => Err(String::from(INVALID_URL)),
+        }
+    }
+
+    fn into_info(self, provider: Provider, head_url: String, branch: &str) -> RepositoryInfo {
+        RepositoryInfo {
+            provider,
+            url: head_url,
+            clone_url: self.clone_url,
+            branch: branch.to_owned(),
+            author: self.owner.join("/"),
+            name: self.name,
+        }
+    }
+}
+
+/// Turns scp-like `user@host:path` into `host/path`.
+/// Returns `None` for any other URL.
+pub fn scp_address(url: &str) -> Option<String> {
+    let (user_host, path) = url.split_once(':')?;
+    let (_, host) = user_host.split_once('@')?;
+    if host.is_empty() || host.contains('/') || path.starts_with('/') {
+        return None;
+    }
+    Some(format!("{}/{}", host, path))
+}
+
+/// API endpoint asked with conditional requests, so unchanged
+/// responses do not count against the rate limit. Once the
+/// limit is exhausted, it is not asked until it is reset.
+pub struct Endpoint {
+    url: String,
+    state: Mutex<EndpointState>,
+}
+
+#[derive(Debug, Default)]
+struct EndpointState {
+    /// `ETag` of `body`, sent back in `If-None-Match`.
+    etag: Option<String>,
+    body: String,
+    exhausted_until: Option<DateTime<Utc>>,
+}
+
+impl Endpoint {
+    pub fn new(url: String) -> Self {
+        Endpoint {
+            url,
+            state: Mutex::new(EndpointState::default()),
+        }
+    }
+
+    /// Sends GET request with the token in `auth` header
+    /// and reads the JSON response.
+    pub async fn get_json<T: DeserializeOwned>(
+        &self,
+        client: &Client,
+        auth: (&str, String),
+    ) -> Result<T, HeadError> {
+        let (etag, exhausted_until) = {
+            let state = self.state.lock().unwrap();
+            (state.etag.clone(), state.exhausted_until)
+        };
+        if let Some(wait) = exhausted_until.and_then(until) {
+            return Err(HeadError::RateLimited(wait));
+        }
+
+        let mut request = client
+            .get(&self.url)
+            .header(auth.0, auth.1)
+            .header("User-Agent", "request");
+        if let Some(etag) = etag {
+            request = request.header("If-None-Match", etag);
+        }
+        let response = request.send().await?;
+        let status = response.status();
+        let headers = response.headers().clone();
+        let remaining: Option<u64> = header(&headers, "x-ratelimit-remaining");
+        let reset =
+            header(&headers, "x-ratelimit-reset").and_then(|r| DateTime::from_timestamp(r, 0));
+        if let Some(remaining) = remaining {
+            debug!("Rate limit: {} requests remaining.", remaining);
+        }
+        self.state.lock().unwrap().exhausted_until = reset.filter(|_| remaining == Some(0));
+
+        let body = if status == StatusCode::NOT_MODIFIED {
+            self.state.lock().unwrap().body.clone()
+        } else if status.is_success() {
+            let etag = headers.get("etag").and_then(|e| e.to_str().ok());
+            let etag = etag.map(str::to_owned);
+            let body = response.text().await?;
+            let mut state = self.state.lock().unwrap();
+            state.etag = etag;
+            state.body = body.clone();
+            body
+        } else if let Some(secs) = header(&headers, "retry-after") {
+            return Err(HeadError::RateLimited(Duration::from_secs(secs)));
+        } else if let Some(wait) = reset.filter(|_| remaining == Some(0)).and_then(until) {
+            return Err(HeadError::RateLimited(wait));
+        } else {
+            return Err(HeadError::Status(status));
+        };
+        Ok(serde_json::from_str(&body)?)
+    }
+}
+
+/// Value of the numeric header `name`.
+fn header<T: FromStr>(headers: &HeaderMap, name: &str) -> Option<T> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// Time left until `time`, `None` if it has passed.
+fn until(time: DateTime<Utc>) -> Option<Duration> {
+    (time - Utc::now()).to_std().ok().filter(|d| !d.is_zero())
+}
+
+/// Percent-encodes `component` so it can be used
+/// as a single segment of URL path.
+fn encode(component: &str) -> String {
+    let mut encoded = String::new();
+    for byte in component.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_deployer::stub;
+
+    #[test]
+    fn test_ssh_url() {
+        let url = "git@github.com:Makefolder/deployer.git";
+        let info = repository_info(Provider::GitHub, url, "master", None).unwrap();
+        assert_eq!(
+            info.url,
+            "https://api.github.com/repos/Makefolder/deployer/commits/master"
+        );
+        assert_eq!(info.clone_url, url);
+        assert_eq!(info.name, "deployer");
+        assert_eq!(scp_address("https://user@github.com/owner/repo"), None);
+    }
+
+    #[test]
+    fn test_invalid_urls() {
+        for url in [
+            "github.com//deployer",
+            "github.com/Makefolder/",
+            "github.com",
+        ] {
+            let error = repository_info(Provider::GitHub, url, "master", None).unwrap_err();
+            assert_eq!(error, INVALID_URL, "{}", url);
+        }
+        let error = repository_info(Provider::Gitea, "host/a/b/c", "main", None).unwrap_err();
+        assert_eq!(error, INVALID_URL);
+        let error = repository_info(Provider::Git, "/srv/git/app", "", None).unwrap_err();
+        assert_eq!(error, "No main branch specified!");
+    }
+
+    #[test]
+    fn test_commit_display() {
+        let commit = Commit {
+            sha: String::from("abc123"),
+            message: Some(String::from("Fix build\n\nDetails")),
+            author: Some(String::from("Jane")),
+        };
+        assert_eq!(commit.to_string(), "abc123 \"Fix build\" by Jane");
+    }
+
+    #[tokio::test]
+    async fn test_conditional_request() {
+        let body = r#"{ "sha": "abc123" }"#;
+        let (url, server) = stub::serve(vec![
+            stub::Response::new("200 OK", body)
+                .header("ETag", "\"v1\"")
+                .header("X-RateLimit-Remaining", "59"),
+            stub::Response::new("304 Not Modified", ""),
+        ]);
+        let endpoint = Endpoint::new(url);
+        let client = Client::builder().no_proxy().build().unwrap();
+        for _ in 0..2 {
+            let value: serde_json::Value = endpoint.get_json(&client, auth()).await.unwrap();
+            assert_eq!(value["sha"], "abc123");
+        }
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests[0].header("if-none-match"), None);
+        assert_eq!(requests[1].header("if-none-match"), Some("\"v1\""));
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_rate_limit() {
+        let reset = (Utc::now().timestamp() + 120).to_string();
+        let (url, server) = stub::serve(vec![
+            stub::Response::new("200 OK", "{}")
+                .header("X-RateLimit-Remaining", "0")
+                .header("X-RateLimit-Reset", &reset),
+            stub::Response::new("429 Too Many Requests", "").header("Retry-After", "30"),
+        ]);
+        let endpoint = Endpoint::new(url);
+        let client = Client::builder().no_proxy().build().unwrap();
+        let _: serde_json::Value = endpoint.get_json(&client, auth()).await.unwrap();
+
+        // Not asked again until the reset
+        let limited = endpoint
+            .get_json::<serde_json::Value>(&client, auth())
+            .await;
+        let Err(HeadError::RateLimited(wait)) = limited else {
+            panic!("rate limit should be exhausted");
+        };
+        assert!(wait > Duration::from_secs(100) && wait <= Duration::from_secs(120));
+
+        endpoint.state.lock().unwrap().exhausted_until = None;
+        let retry = endpoint
+            .get_json::<serde_json::Value>(&client, auth())
+            .await;
+        assert!(matches!(retry, Err(HeadError::RateLimited(wait)) if wait.as_secs() == 30));
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+
+    fn auth() -> (&'static str, String) {
+        ("Authorization", String::from("token secret"))
+    }
+}
@@ -0,0 +1,366 @@
+// Credentials to clone with: the token (sent as username and
+// password in the style of the provider) or an SSH key. Host keys
+// of SSH remotes are verified against the known_hosts file.
+
+use crate::generate_conf::file_struct::{ConfigFile, Secret};
+use crate::log;
+use crate::run_deployer::paths;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Local};
+use git2::cert::Cert;
+use git2::{CertificateCheckStatus, Cred, CredentialType, ErrorClass, ErrorCode, RemoteCallbacks};
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct SshKey {
+    pub key: PathBuf,
+    pub passphrase: Option<Secret>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Credentials {
+    pub token: Secret,
+    pub ssh_key: Option<SshKey>,
+    pub known_hosts: PathBuf,
+    /// Host of the repository. The token and SSH key are not
+    /// offered to others, e.g. submodules hosted elsewhere.
+    pub host: Option<String>,
+}
+
+impl Credentials {
+    /// Credentials for the repository cloned from `clone_url`.
+    /// Expects paths of the config to be resolved already.
+    pub fn from_config(config: &ConfigFile, clone_url: &str) -> Self {
+        let ssh_key = config.ssh_key.as_ref().map(|key| SshKey {
+            key: key.clone(),
+            passphrase: config.ssh_passphrase.clone(),
+        });
+        Credentials {
+            token: Secret::new(config.token().to_owned()),
+            ssh_key,
+            known_hosts: config
+                .known_hosts
+                .clone()
+                .unwrap_or_else(default_known_hosts),
+            host: url_host(clone_url),
+        }
+    }
+
+    /// Whether `url` is on the repository's host. Relative
+    /// URLs of submodules must be resolved first.
+    fn is_own_host(&self, url: &str) -> bool {
+        url_host(url) == self.host
+    }
+
+    /// Callbacks answering the remote: with the SSH key if the remote
+    /// asks for one and with `username` and `password` otherwise,
+    /// only if `url` is on the repository's host.
+    /// Host keys of SSH remotes must be in `known_hosts`.
+    pub fn callbacks(&self, url: &str, username: &str, password: &str) -> RemoteCallbacks<'_> {
+        let (username, password) = (username.to_owned(), password.to_owned());
+        let own_host = self.is_own_host(url);
+        // git2 tells the host only, not the port
+        let port = ssh_port(url);
+        let mut callbacks = RemoteCallbacks::new();
+        let mut attempts = 0;
+        callbacks.credentials(move |_, username_from_url, allowed| {
+            let ssh_username = username_from_url.unwrap_or("git");
+            if allowed.contains(CredentialType::USERNAME) {
+                return Cred::username(ssh_username);
+            }
+            if !own_host {
+                return Err(git2::Error::from_str(
+                    "The remote asks for credentials, but it is not on the repository's host",
+                ));
+            }
+            // git2 keeps asking for as long as the remote rejects them
+            attempts += 1;
+            if attempts > 1 {
+                return Err(git2::Error::from_str(
+                    "Credentials were rejected by the remote",
+                ));
+            }
+            if !allowed.contains(CredentialType::SSH_KEY) {
+                return Cred::userpass_plaintext(&username, &password);
+            }
+            match &self.ssh_key {
+                Some(ssh) => Cred::ssh_key(
+                    ssh_username,
+                    None,
+                    &ssh.key,
+                    ssh.passphrase.as_ref().map(Secret::expose),
+                ),
+                None => Err(git2::Error::from_str(
+                    "The remote asks for SSH key but `ssh_key` is not set",
+                )),
+            }
+        });
+        callbacks.certificate_check(move |cert, host| self.check_host(cert, host, port));
+        callbacks
+    }
+
+    fn check_host(
+        &self,
+        cert: &Cert,
+        host: &str,
+        port: u16,
+    ) -> Result<CertificateCheckStatus, git2::Error> {
+        // TLS certificates are verified by git2 itself
+        let Some(hostkey) = cert.as_hostkey() else {
+            return Ok(CertificateCheckStatus::CertificatePassthrough);
+        };
+        let Some(key) = hostkey.hostkey() else {
+            return Err(git2::Error::from_str(&format!(
+                "Host key of {} cannot be verified",
+                host
+            )));
+        };
+        let known_hosts = fs::read_to_string(&self.known_hosts).map_err(|e| {
+            git2::Error::from_str(&format!(
+                "Failed to read {}: {}",
+                self.known_hosts.display(),
+                e
+            ))
+        })?;
+        let message = match host_key(&known_hosts, host, port, key) {
+            HostKey::Known => return Ok(CertificateCheckStatus::CertificateOk),
+            HostKey::Unknown => format!(
+                "Host key of {} is not in {}",
+                host_name(host, port),
+                self.known_hosts.display()
+            ),
+            HostKey::Revoked => format!(
+                "Host key of {} is revoked in {}",
+                host_name(host, port),
+                self.known_hosts.display()
+            ),
+        };
+        log!("{}, refusing to connect.", message);
+        Err(git2::Error::new(
+            ErrorCode::Certificate,
+            ErrorClass::Ssh,
+            message,
+        ))
+    }
+}
+
+fn default_known_hosts() -> PathBuf {
+    paths::resolve(Path::new("~/.ssh/known_hosts"), Path::new("/"))
+}
+
+/// Host of the remote at `url` in lowercase,
+/// `None` for local paths.
+fn url_host(url: &str) -> Option<String> {
+    let authority = match url.split_once("://") {
+        Some((_, rest)) => rest.split('/').next().unwrap_or_default(),
+        // scp-like `user@host:path`
+        None => match url.split_once(':') {
+            Some((user_host, _)) if !user_host.contains('/') => user_host,
+            _ => return None,
+        },
+    };
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    // `[::1]:2222`
+    let host = match host_port.strip_prefix('[') {
+        Some(rest) => rest.split(']').next().unwrap_or_default(),
+        None => host_port.split(':').next().unwrap_or_default(),
+    };
+    (!host.is_empty()).then(|| host.to_ascii_lowercase())
+}
+
+/// Port SSH connects to for `url`: the one in
+/// `ssh://host:port/path` or 22.
+fn ssh_port(url: &str) -> u16 {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        // scp-like `user@host:path` has no port
+        return 22;
+    };
+    if !scheme.contains("ssh") {
+        return 22;
+    }
+    let authority = rest.split('/').next().unwrap_or_default();
+    let host_port = authority.rsplit_once('@').map_or(authority, |(_, h)| h);
+    // `[::1]:2222`
+    let port = match host_port.rsplit_once(']') {
+        Some((_, port)) => port.strip_prefix(':'),
+        None => host_port.split_once(':').map(|(_, port)| port),
+    };
+    port.and_then(|port| port.parse().ok()).unwrap_or(22)
+}
+
+/// Name of the host in known_hosts: `host`
+/// or `[host]:port` if the port is not 22.
+fn host_name(host: &str, port: u16) -> String {
+    match port {
+        22 => host.to_owned(),
+        port => format!("[{}]:{}", host, port),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum HostKey {
+    Known,
+    Unknown,
+    Revoked,
+}
+
+/// Looks `key` of `host` up in contents of known_hosts file. Both
+/// plain and hashed host names are understood, a `@revoked` line
+/// for the key wins over the others. `@cert-authority` lines
+/// are ignored.
+fn host_key(known_hosts: &str, host: &str, port: u16, key: &[u8]) -> HostKey {
+    let name = host_name(host, port);
+    let mut found = HostKey::Unknown;
+    for line in known_hosts.lines() {
+        let mut fields = line.split_whitespace().peekable();
+        let marker = fields.next_if(|field| field.starts_with('@'));
+        let (Some(hosts), Some(_), Some(encoded)) = (fields.next(), fields.next(), fields.next())
+        else {
+            continue;
+        };
+        if hosts.starts_with('#') || !STANDARD.decode(encoded).is_ok_and(|k| k == key) {
+            continue;
+        }
+        let host_matches = match hosts.strip_prefix("|1|") {
+            Some(hashed) => hashed_matches(hashed, &name),
+            None => hosts
+                .split(',')
+                .any(|pattern| pattern == "*" || pattern.eq_ignore_ascii_case(&name)),
+        };
+        match marker {
+            _ if !host_matches => {}
+            Some("@revoked") => return HostKey::Revoked,
+            Some(_) => {}
+            None => found = HostKey::Known,
+        }
+    }
+    found
+}
+
+/// `salt|hash` where hash is HMAC-SHA1 of the host name.
+fn hashed_matches(hashed: &str, host: &str) -> bool {
+    let Some((salt, hash)) = hashed.split_once('|') else {
+        return false;
+    };
+    let (Ok(salt), Ok(hash)) = (STANDARD.decode(salt), STANDARD.decode(hash)) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha1>::new_from_slice(&salt) else {
+        return false;
+    };
+    mac.update(host.as_bytes());
+    mac.verify_slice(&hash).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &[u8] = b"\0\0\0\x0bssh-ed25519\0\0\0\x04test";
+
+    #[test]
+    fn test_plain_known_host() {
+        let encoded = STANDARD.encode(KEY);
+        let known_hosts = format!(
+            "# comment\nother.com ssh-ed25519 {0}\ngit.example.com,10.0.0.1 ssh-ed25519 {0}\n",
+            encoded
+        );
+        let known = |host, key| host_key(&known_hosts, host, 22, key);
+        assert_eq!(known("git.example.com", KEY), HostKey::Known);
+        assert_eq!(known("10.0.0.1", KEY), HostKey::Known);
+        assert_eq!(known("evil.com", KEY), HostKey::Unknown);
+        assert_eq!(known("git.example.com", b"other key"), HostKey::Unknown);
+        assert_eq!(
+            host_key(&known_hosts, "git.example.com", 2222, KEY),
+            HostKey::Unknown
+        );
+
+        let with_port = format!("[git.example.com]:2222 ssh-ed25519 {}", encoded);
+        assert_eq!(
+            host_key(&with_port, "git.example.com", 2222, KEY),
+            HostKey::Known
+        );
+        assert_eq!(
+            host_key(&with_port, "git.example.com", 22, KEY),
+            HostKey::Unknown
+        );
+        assert_eq!(
+            host_key(&with_port, "git.example.com", 2200, KEY),
+            HostKey::Unknown
+        );
+    }
+
+    #[test]
+    fn test_revoked_host_key() {
+        let encoded = STANDARD.encode(KEY);
+        let known_hosts = format!(
+            "git.example.com ssh-ed25519 {0}\n@revoked * ssh-ed25519 {0}\n",
+            encoded
+        );
+        assert_eq!(
+            host_key(&known_hosts, "git.example.com", 22, KEY),
+            HostKey::Revoked
+        );
+        // Revoked for another host only
+        let known_hosts = format!(
+            "git.example.com ssh-ed25519 {0}\n@revoked other.com ssh-ed25519 {0}\n",
+            encoded
+        );
+        assert_eq!(
+            host_key(&known_hosts, "git.example.com", 22, KEY),
+            HostKey::Known
+        );
+    }
+
+    #[test]
+    fn test_own_host() {
+        let config = ConfigFile::default();
+        let credentials = Credentials::from_config(&config, "https://GitHub.com/owner/repo.git");
+        assert!(credentials.is_own_host("git@github.com:owner/lib.git"));
+        assert!(credentials.is_own_host("ssh://git@github.com:22/owner/lib.git"));
+        assert!(!credentials.is_own_host("../lib.git"));
+        assert!(!credentials.is_own_host("https://gitlab.com/owner/lib.git"));
+        assert!(!credentials.is_own_host("https://github.com.evil.com/owner/lib.git"));
+        assert!(!credentials.is_own_host("/srv/git/lib.git"));
+
+        assert_eq!(url_host("file:///srv/git/repo.git"), None);
+        assert_eq!(
+            url_host("ssh://[::1]:2222/repo.git").as_deref(),
+            Some("::1")
+        );
+    }
+
+    #[test]
+    fn test_ssh_port() {
+        assert_eq!(ssh_port("git@github.com:owner/repo.git"), 22);
+        assert_eq!(ssh_port("ssh://git@git.example.com/repo.git"), 22);
+        assert_eq!(ssh_port("ssh://git@git.example.com:2222/repo.git"), 2222);
+        assert_eq!(ssh_port("ssh://[::1]:2222/repo.git"), 2222);
+        assert_eq!(ssh_port("https://git.example.com:8443/repo.git"), 22);
+    }
+
+    #[test]
+    fn test_hashed_known_host() {
+        let salt = b"0123456789abcdefghij";
+        let mut mac = Hmac::<Sha1>::new_from_slice(salt).unwrap();
+        mac.update(b"github.com");
+        let hash = mac.finalize().into_bytes();
+        let known_hosts = format!(
+            "|1|{}|{} ssh-ed25519 {}",
+            STANDARD.encode(salt),
+            STANDARD.encode(hash),
+            STANDARD.encode(KEY)
+        );
+        assert_eq!(
+            host_key(&known_hosts, "github.com", 22, KEY),
+            HostKey::Known
+        );
+        assert_eq!(
+            host_key(&known_hosts, "gitlab.com", 22, KEY),
+            HostKey::Unknown
+        );
+    }
+}
@@ -0,0 +1,113 @@
+use super::{Commit, Credentials, HeadFuture, SourceProvider};
+use crate::generate_conf::file_struct::Provider;
+use crate::run_deployer::pull::RepositoryInfo;
+use git2::{Direction, ErrorClass, ErrorCode, Remote, RemoteCallbacks};
+use reqwest::Client;
+use tokio::task;
+
+/// `repository` from the config is passed to git as is,
+/// it knows better what is valid.
+pub fn repository_info(url: &str, branch: &str) -> RepositoryInfo {
+    let name = url.trim_end_matches('/').trim_end_matches(".git");
+    let name = name.rsplit(['/', ':']).next().unwrap_or_default();
+    RepositoryInfo {
+        provider: Provider::Git,
+        // The remote itself is asked for new commits
+        url: url.to_owned(),
+        clone_url: url.to_owned(),
+        branch: branch.to_owned(),
+        author: String::new(),
+        name: name.to_owned(),
+    }
+}
+
+/// Any git remote. Has no API, new commits
+/// are detected with `ls_remote`.
+pub struct Git {
+    url: String,
+    branch: String,
+    credentials: Credentials,
+}
+
+impl Git {
+    pub fn new(repository: &RepositoryInfo, credentials: Credentials) -> Self {
+        Git {
+            url: repository.clone_url.clone(),
+            branch: repository.branch.clone(),
+            credentials,
+        }
+    }
+}
+
+impl SourceProvider for Git {
+    fn head<'a>(&'a self, _: &'a Client) -> HeadFuture<'a> {
+        let url = self.url.clone();
+        let branch = self.branch.clone();
+        let credentials = self.credentials.clone();
+        Box::pin(async move {
+            let commit = task::spawn_blocking(move || ls_remote(&url, &branch, &credentials))
+                .await
+                .map_err(|e| git2::Error::from_str(&e.to_string()))??;
+            Ok(commit)
+        })
+    }
+
+    fn clone_url(&self) -> &str {
+        &self.url
+    }
+
+    fn callbacks(&self, url: &str) -> RemoteCallbacks<'_> {
+        self.credentials
+            .callbacks(url, "git", self.credentials.token.expose())
+    }
+}
+
+/// Asks the remote at `url` for the commit `branch` points to,
+/// the same way `git ls-remote` does. Works with any URL git
+/// understands, including `file://` and plain paths.
+///
+/// Blocks until the remote answers.
+fn ls_remote(url: &str, branch: &str, credentials: &Credentials) -> Result<Commit, git2::Error> {
+    let mut remote = Remote::create_detached(url)?;
+    let callbacks: RemoteCallbacks = credentials.callbacks(url, "git", credentials.token.expose());
+    let connection = remote.connect_auth(Direction::Fetch, Some(callbacks), None)?;
+
+    let name = format!("refs/heads/{}", branch);
+    let head = connection.list()?.iter().find(|head| head.name() == name);
+    match head {
+        Some(head) => Ok(Commit {
+            sha: head.oid().to_string(),
+            ..Default::default()
+        }),
+        None => Err(git2::Error::new(
+            ErrorCode::NotFound,
+            ErrorClass::Reference,
+            format!("Branch {} not found in {}", branch, url),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_deployer::stub;
+
+    #[test]
+    fn test_repository_info() {
+        let url = "git@git.example.com:team/project.git";
+        let info = repository_info(url, "main");
+        assert_eq!(info.clone_url, url);
+        assert_eq!(info.name, "project");
+    }
+
+    #[test]
+    fn test_ls_remote_file_url() {
+        let dir = stub::TempDir::new("ls-remote");
+        let sha = stub::init_repo(&dir, "main");
+
+        let url = format!("file://{}", dir.display());
+        let credentials = Credentials::default();
+        assert_eq!(ls_remote(&url, "main", &credentials).unwrap().sha, sha);
+        assert!(ls_remote(&url, "missing", &credentials).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use super::{encode, Commit, Credentials, Endpoint, ForgeUrl, HeadFuture, SourceProvider};
+use crate::generate_conf::file_struct::Provider;
+use crate::run_deployer::pull::RepositoryInfo;
+use git2::RemoteCallbacks;
+use reqwest::Client;
+use serde_derive::Deserialize;
+
+/// Branch as returned by `/repos/{owner}/{repo}/branches/{branch}`.
+#[derive(Debug, Deserialize)]
+struct Branch {
+    commit: BranchCommit,
+}
+
+#[derive(Debug, Deserialize)]
+struct BranchCommit {
+    id: String,
+    message: Option<String>,
+    author: Option<Author>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    name: String,
+}
+
+/// `repository` from the config is `host/owner/repo`.
+pub fn repository_info(
+    url: &str,
+    branch: &str,
+    api_url: Option<&str>,
+) -> Result<RepositoryInfo, String> {
+    let repository = ForgeUrl::parse(url)?.single_owner()?;
+    let default_api_url = format!("https://{}/api/v1", repository.host);
+    let head_url = format!(
+        "{}/repos/{}/{}/branches/{}",
+        api_url.unwrap_or(&default_api_url).trim_end_matches('/'),
+        encode(&repository.owner[0]),
+        encode(&repository.name),
+        encode(branch)
+    );
+    Ok(repository.into_info(Provider::Gitea, head_url, branch))
+}
+
+/// Gitea and its forks such as Forgejo.
+pub struct Gitea {
+    head: Endpoint,
+    clone_url: String,
+    credentials: Credentials,
+}
+
+impl Gitea {
+    pub fn new(repository: &RepositoryInfo, credentials: Credentials) -> Self {
+        Gitea {
+            head: Endpoint::new(repository.url.clone()),
+            clone_url: repository.clone_url.clone(),
+            credentials,
+        }
+    }
+}
+
+impl SourceProvider for Gitea {
+    fn head<'a>(&'a self, client: &'a Client) -> HeadFuture<'a> {
+        Box::pin(async move {
+            let auth = (
+                "Authorization",
+                format!("token {}", self.credentials.token.expose()),
+            );
+            let branch: Branch = self.head.get_json(client, auth).await?;
+            Ok(Commit {
+                sha: branch.commit.id,
+                message: branch.commit.message,
+                author: branch.commit.author.map(|a| a.name),
+            })
+        })
+    }
+
+    fn clone_url(&self) -> &str {
+        &self.clone_url
+    }
+
+    fn callbacks(&self, url: &str) -> RemoteCallbacks<'_> {
+        // Gitea takes the token as username if the password is `x-oauth-basic`
+        self.credentials
+            .callbacks(url, self.credentials.token.expose(), "x-oauth-basic")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_conf::file_struct::Secret;
+    use crate::run_deployer::stub;
+
+    #[tokio::test]
+    async fn test_head_from_stub_server() {
+        let body = r#"{ "name": "main", "commit": { "id": "0123abcd", "message": "Fix" } }"#;
+        let (api_url, server) = stub::serve(vec![stub::Response::new("200 OK", body)]);
+
+        let api_url = format!("{}/", api_url);
+        let repository = repository_info("localhost/org/repo", "main", Some(&api_url)).unwrap();
+        assert_eq!(repository.clone_url, "https://localhost/org/repo.git");
+        let credentials = Credentials {
+            token: Secret::new(String::from("secret")),
+            ..Default::default()
+        };
+        let gitea = Gitea::new(&repository, credentials);
+        let client = Client::builder().no_proxy().build().unwrap();
+        let commit = gitea.head(&client).await.unwrap();
+        assert_eq!(commit.sha, "0123abcd");
+        assert_eq!(commit.message.as_deref(), Some("Fix"));
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[0].line,
+            "GET /repos/org/repo/branches/main HTTP/1.1"
+        );
+        assert_eq!(requests[0].header("authorization"), Some("token secret"));
+    }
+}
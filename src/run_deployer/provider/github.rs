@@ -0,0 +1,196 @@
+use super::github_app::App;
+use super::{Commit, Credentials, Endpoint, ForgeUrl, HeadFuture, PrepareFuture, SourceProvider};
+use crate::generate_conf::file_struct::Provider;
+use crate::run_deployer::pull::RepositoryInfo;
+use git2::RemoteCallbacks;
+use reqwest::Client;
+use serde_derive::Deserialize;
+
+/// API of github.com, the only host allowed.
+const API_URL: &str = "https://api.github.com";
+
+/// Commit as returned by `/repos/{owner}/{repo}/commits/{branch}`.
+#[derive(Debug, Deserialize)]
+struct Response {
+    sha: String,
+    commit: Details,
+}
+
+#[derive(Debug, Deserialize)]
+struct Details {
+    message: Option<String>,
+    author: Option<Author>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    name: String,
+}
+
+/// `repository` from the config is `github.com/owner/repo`.
+pub fn repository_info(
+    url: &str,
+    branch: &str,
+    configured_api_url: Option<&str>,
+) -> Result<RepositoryInfo, String> {
+    let repository = ForgeUrl::parse(url)?.single_owner()?;
+    if repository.host != "github.com" {
+        return Err(String::from("Invalid repository domain!"));
+    }
+    let head_url = format!(
+        "{}/repos/{}/{}/commits/{}",
+        api_url(configured_api_url).trim_end_matches('/'),
+        repository.owner[0],
+        repository.name,
+        branch
+    );
+    Ok(repository.into_info(Provider::GitHub, head_url, branch))
+}
+
+/// `api_url` from the config or the default one.
+pub fn api_url(configured: Option<&str>) -> &str {
+    configured.unwrap_or(API_URL)
+}
+
+pub struct GitHub {
+    head: Endpoint,
+    clone_url: String,
+    credentials: Credentials,
+    /// Installation tokens of the app are used instead
+    /// of the token from `credentials` if it is set.
+    app: Option<App>,
+}
+
+impl GitHub {
+    pub fn new(repository: &RepositoryInfo, credentials: Credentials, app: Option<App>) -> Self {
+        GitHub {
+            head: Endpoint::new(repository.url.clone()),
+            clone_url: repository.clone_url.clone(),
+            credentials,
+            app,
+        }
+    }
+}
+
+impl SourceProvider for GitHub {
+    fn head<'a>(&'a self, client: &'a Client) -> HeadFuture<'a> {
+        Box::pin(async move {
+            let token = match &self.app {
+                Some(app) => app.token(client).await?,
+                None => self.credentials.token.clone(),
+            };
+            let auth = ("Authorization", format!("token {}", token.expose()));
+            let response: Response = self.head.get_json(client, auth).await?;
+            Ok(Commit {
+                sha: response.sha,
+                message: response.commit.message,
+                author: response.commit.author.map(|a| a.name),
+            })
+        })
+    }
+
+    fn clone_url(&self) -> &str {
+        &self.clone_url
+    }
+
+    fn prepare<'a>(&'a self, client: &'a Client) -> PrepareFuture<'a> {
+        Box::pin(async move {
+            if let Some(app) = &self.app {
+                app.token(client).await?;
+            }
+            Ok(())
+        })
+    }
+
+    fn callbacks(&self, url: &str) -> RemoteCallbacks<'_> {
+        let token = match &self.app {
+            Some(app) => match app.cached_token() {
+                Some(token) => token,
+                None => {
+                    let mut callbacks = RemoteCallbacks::new();
+                    callbacks.credentials(|_, _, _| {
+                        Err(git2::Error::from_str(
+                            "Installation token of the GitHub App was not requested",
+                        ))
+                    });
+                    return callbacks;
+                }
+            },
+            None => self.credentials.token.clone(),
+        };
+        self.credentials
+            .callbacks(url, "x-access-token", token.expose())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_deployer::stub;
+
+    #[test]
+    fn test_repository_info() {
+        let info = repository_info("github.com/Makefolder/deployer", "master", None).unwrap();
+        assert_eq!(
+            info.url,
+            "https://api.github.com/repos/Makefolder/deployer/commits/master"
+        );
+        assert_eq!(info.clone_url, "https://github.com/Makefolder/deployer.git");
+        assert_eq!(info.author, "Makefolder");
+        assert_eq!(info.name, "deployer");
+
+        let error = repository_info("gitlab.com/Makefolder/deployer", "master", None);
+        assert_eq!(error.unwrap_err(), "Invalid repository domain!");
+    }
+
+    #[test]
+    fn test_parse_commit() {
+        let body =
+            r#"{ "sha": "abc123", "commit": { "message": "Fix", "author": { "name": "Jane" } } }"#;
+        let response: Response = serde_json::from_str(body).unwrap();
+        assert_eq!(response.sha, "abc123");
+        assert_eq!(response.commit.author.unwrap().name, "Jane");
+    }
+
+    #[tokio::test]
+    async fn test_head_as_app() {
+        let dir = stub::TempDir::new("gh-app");
+        let token = r#"{ "token": "ghs_app", "expires_at": "2999-01-01T00:00:00Z" }"#;
+        let commit = r#"{ "sha": "abc123", "commit": { "message": "Fix" } }"#;
+        let (api_url, server) = stub::serve(vec![
+            stub::Response::new("201 Created", token),
+            stub::Response::new("200 OK", commit),
+        ]);
+
+        let repository = repository_info("github.com/owner/repo", "main", Some(&api_url));
+        let repository = repository.unwrap();
+        let app = App::new(&stub::test_app(&dir), &api_url);
+        let github = GitHub::new(&repository, Credentials::default(), Some(app));
+        let client = Client::builder().no_proxy().build().unwrap();
+        assert_eq!(github.head(&client).await.unwrap().sha, "abc123");
+
+        let requests = server.join().unwrap();
+        assert_eq!(
+            requests[1].line,
+            "GET /repos/owner/repo/commits/main HTTP/1.1"
+        );
+        assert_eq!(requests[1].header("authorization"), Some("token ghs_app"));
+    }
+
+    #[tokio::test]
+    async fn test_prepare_requests_token() {
+        let dir = stub::TempDir::new("gh-prep");
+        let token = r#"{ "token": "ghs_app", "expires_at": "2999-01-01T00:00:00Z" }"#;
+        let (api_url, server) = stub::serve(vec![stub::Response::new("201 Created", token)]);
+
+        let repository = repository_info("github.com/owner/repo", "main", Some(&api_url));
+        let app = App::new(&stub::test_app(&dir), &api_url);
+        let github = GitHub::new(&repository.unwrap(), Credentials::default(), Some(app));
+        let client = Client::builder().no_proxy().build().unwrap();
+        // A pushed commit is cloned without asking for `head`
+        github.prepare(&client).await.unwrap();
+        let cached = github.app.as_ref().unwrap().cached_token();
+        assert_eq!(cached.unwrap().expose(), "ghs_app");
+        assert_eq!(server.join().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,170 @@
+// Authentication as a GitHub App: a JWT signed with the app's
+// private key is exchanged for an installation token, which is
+// then used like a personal token until shortly before it expires.
+
+use super::HeadError;
+use crate::generate_conf::file_struct::{GitHubApp, Secret};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{Algorithm, EncodingKey, Header};
+use reqwest::Client;
+use serde_derive::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// The token is requested again if it expires sooner than this.
+const REFRESH_MARGIN: Duration = Duration::minutes(5);
+
+/// Claims GitHub expects in the JWT.
+#[derive(Debug, Serialize)]
+struct Claims {
+    iat: i64,
+    exp: i64,
+    iss: String,
+}
+
+/// Response of `/app/installations/{id}/access_tokens`.
+#[derive(Debug, Deserialize)]
+struct Response {
+    token: Secret,
+    expires_at: String,
+}
+
+#[derive(Debug, Clone)]
+struct InstallationToken {
+    token: Secret,
+    expires_at: DateTime<Utc>,
+}
+
+pub struct App {
+    app_id: u64,
+    token_url: String,
+    private_key: PathBuf,
+    cached: Mutex<Option<InstallationToken>>,
+}
+
+impl App {
+    pub fn new(app: &GitHubApp, api_url: &str) -> Self {
+        App {
+            app_id: app.app_id,
+            token_url: format!(
+                "{}/app/installations/{}/access_tokens",
+                api_url.trim_end_matches('/'),
+                app.installation_id
+            ),
+            private_key: app.private_key.clone(),
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// Installation token, a new one is requested
+    /// if the cached one is about to expire.
+    pub async fn token(&self, client: &Client) -> Result<Secret, HeadError> {
+        if let Some(cached) = self.cached.lock().unwrap().as_ref() {
+            if cached.expires_at - REFRESH_MARGIN > Utc::now() {
+                return Ok(cached.token.clone());
+            }
+        }
+
+        let key = read_key(&self.private_key).map_err(HeadError::Auth)?;
+        let jwt = sign(self.app_id, &key).map_err(|e| HeadError::Auth(e.to_string()))?;
+        let response = client
+            .post(&self.token_url)
+            .header("Authorization", format!("Bearer {}", jwt))
+            .header("Accept", "application/vnd.github+json")
+            .header("User-Agent", "request")
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            return Err(HeadError::Status(response.status()));
+        }
+        let response: Response = serde_json::from_str(&response.text().await?)?;
+        let expires_at = DateTime::parse_from_rfc3339(&response.expires_at)
+            .map_err(|e| HeadError::Auth(format!("invalid `expires_at`: {}", e)))?
+            .with_timezone(&Utc);
+
+        let token = response.token;
+        *self.cached.lock().unwrap() = Some(InstallationToken {
+            token: token.clone(),
+            expires_at,
+        });
+        Ok(token)
+    }
+
+    /// Last installation token received, without asking for a new
+    /// one. `None` if `token` has not been called yet.
+    pub fn cached_token(&self) -> Option<Secret> {
+        let cached = self.cached.lock().unwrap();
+        cached.as_ref().map(|c| c.token.clone())
+    }
+}
+
+/// Reads the app's private key, also used to check the config.
+pub fn read_key(path: &Path) -> Result<EncodingKey, String> {
+    let pem =
+        fs::read(path).map_err(|e| format!("failed to read \"{}\": {}", path.display(), e))?;
+    EncodingKey::from_rsa_pem(&pem)
+        .map_err(|e| format!("\"{}\" is not an RSA private key: {}", path.display(), e))
+}
+
+/// JWT the app authenticates with, valid for 9 minutes.
+fn sign(app_id: u64, key: &EncodingKey) -> jsonwebtoken::errors::Result<String> {
+    let now = Utc::now().timestamp();
+    let claims = Claims {
+        // Allows for the clock being a bit ahead of GitHub's
+        iat: now - 60,
+        exp: now + 9 * 60,
+        iss: app_id.to_string(),
+    };
+    jsonwebtoken::encode(&Header::new(Algorithm::RS256), &claims, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_deployer::stub;
+
+    #[tokio::test]
+    async fn test_token_is_cached() {
+        let dir = stub::TempDir::new("app");
+        let body = r#"{ "token": "ghs_first", "expires_at": "2999-01-01T00:00:00Z" }"#;
+        let (api_url, server) = stub::serve(vec![stub::Response::new("201 Created", body)]);
+
+        let app = App::new(&stub::test_app(&dir), &api_url);
+        let client = Client::builder().no_proxy().build().unwrap();
+        assert_eq!(app.token(&client).await.unwrap().expose(), "ghs_first");
+        // Answered from the cache, the stub server is gone by now
+        assert_eq!(app.token(&client).await.unwrap().expose(), "ghs_first");
+        assert_eq!(app.cached_token().unwrap().expose(), "ghs_first");
+
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(
+            requests[0].line,
+            "POST /app/installations/42/access_tokens HTTP/1.1"
+        );
+        let jwt = requests[0].header("authorization").unwrap();
+        assert!(jwt.starts_with("Bearer "));
+        assert_eq!(jwt.split('.').count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_expiring_token_is_refreshed() {
+        let dir = stub::TempDir::new("app-exp");
+        let expiring = format!(
+            r#"{{ "token": "ghs_old", "expires_at": "{}" }}"#,
+            (Utc::now() + Duration::minutes(1)).to_rfc3339()
+        );
+        let fresh = r#"{ "token": "ghs_new", "expires_at": "2999-01-01T00:00:00Z" }"#;
+        let (api_url, server) = stub::serve(vec![
+            stub::Response::new("201 Created", &expiring),
+            stub::Response::new("201 Created", fresh),
+        ]);
+
+        let app = App::new(&stub::test_app(&dir), &api_url);
+        let client = Client::builder().no_proxy().build().unwrap();
+        assert_eq!(app.token(&client).await.unwrap().expose(), "ghs_old");
+        assert_eq!(app.token(&client).await.unwrap().expose(), "ghs_new");
+        assert_eq!(server.join().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,104 @@
+use super::{encode, Commit, Credentials, Endpoint, ForgeUrl, HeadFuture, SourceProvider};
+use crate::generate_conf::file_struct::Provider;
+use crate::run_deployer::pull::RepositoryInfo;
+use git2::RemoteCallbacks;
+use reqwest::Client;
+use serde_derive::Deserialize;
+
+/// Branch as returned by
+/// `/projects/{id}/repository/branches/{branch}`.
+#[derive(Debug, Deserialize)]
+struct Branch {
+    commit: BranchCommit,
+}
+
+#[derive(Debug, Deserialize)]
+struct BranchCommit {
+    id: String,
+    message: Option<String>,
+    author_name: Option<String>,
+}
+
+/// `repository` from the config is `host/group/project`,
+/// projects may be nested in subgroups.
+pub fn repository_info(
+    url: &str,
+    branch: &str,
+    api_url: Option<&str>,
+) -> Result<RepositoryInfo, String> {
+    let repository = ForgeUrl::parse(url)?;
+    let default_api_url = format!("https://{}/api/v4", repository.host);
+    let path = format!("{}/{}", repository.owner.join("/"), repository.name);
+    let head_url = format!(
+        "{}/projects/{}/repository/branches/{}",
+        api_url.unwrap_or(&default_api_url).trim_end_matches('/'),
+        encode(&path),
+        encode(branch)
+    );
+    Ok(repository.into_info(Provider::GitLab, head_url, branch))
+}
+
+pub struct GitLab {
+    head: Endpoint,
+    clone_url: String,
+    credentials: Credentials,
+}
+
+impl GitLab {
+    pub fn new(repository: &RepositoryInfo, credentials: Credentials) -> Self {
+        GitLab {
+            head: Endpoint::new(repository.url.clone()),
+            clone_url: repository.clone_url.clone(),
+            credentials,
+        }
+    }
+}
+
+impl SourceProvider for GitLab {
+    fn head<'a>(&'a self, client: &'a Client) -> HeadFuture<'a> {
+        Box::pin(async move {
+            let auth = ("PRIVATE-TOKEN", self.credentials.token.expose().to_owned());
+            let branch: Branch = self.head.get_json(client, auth).await?;
+            Ok(Commit {
+                sha: branch.commit.id,
+                message: branch.commit.message,
+                author: branch.commit.author_name,
+            })
+        })
+    }
+
+    fn clone_url(&self) -> &str {
+        &self.clone_url
+    }
+
+    fn callbacks(&self, url: &str) -> RemoteCallbacks<'_> {
+        self.credentials
+            .callbacks(url, "oauth2", self.credentials.token.expose())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repository_info() {
+        let url = "https://gitlab.example.com/group/subgroup/project.git";
+        let info = repository_info(url, "feature/x", None).unwrap();
+        assert_eq!(
+            info.url,
+            "https://gitlab.example.com/api/v4/projects/group%2Fsubgroup%2Fproject/repository/branches/feature%2Fx"
+        );
+        assert_eq!(info.clone_url, url);
+        assert_eq!(info.author, "group/subgroup");
+        assert_eq!(info.name, "project");
+    }
+
+    #[test]
+    fn test_parse_branch() {
+        let body = r#"{ "name": "main", "commit": { "id": "abc123", "short_id": "abc" } }"#;
+        let branch: Branch = serde_json::from_str(body).unwrap();
+        assert_eq!(branch.commit.id, "abc123");
+        assert_eq!(branch.commit.author_name, None);
+    }
+}
@@ -0,0 +1,49 @@
+// Provider for tests of the code that only
+// talks to `SourceProvider`.
+
+use super::{Commit, HeadError, HeadFuture, SourceProvider};
+use git2::RemoteCallbacks;
+use reqwest::{Client, StatusCode};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Returns commits from `heads` one by one, then replies
+/// with `404 Not Found`. Clones from `clone_url` without
+/// credentials.
+pub struct Mock {
+    pub heads: Mutex<VecDeque<String>>,
+    pub clone_url: String,
+}
+
+impl Mock {
+    pub fn new(heads: &[&str], clone_url: String) -> Self {
+        Mock {
+            heads: Mutex::new(heads.iter().map(|sha| sha.to_string()).collect()),
+            clone_url,
+        }
+    }
+}
+
+impl SourceProvider for Mock {
+    fn head<'a>(&'a self, _: &'a Client) -> HeadFuture<'a> {
+        let sha = self.heads.lock().unwrap().pop_front();
+        Box::pin(async move {
+            match sha {
+                Some(sha) => Ok(Commit {
+                    sha,
+                    message: Some(String::from("Mock commit")),
+                    author: None,
+                }),
+                None => Err(HeadError::Status(StatusCode::NOT_FOUND)),
+            }
+        })
+    }
+
+    fn clone_url(&self) -> &str {
+        &self.clone_url
+    }
+
+    fn callbacks(&self, _url: &str) -> RemoteCallbacks<'_> {
+        RemoteCallbacks::new()
+    }
+}
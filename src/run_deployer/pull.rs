@@ -1,32 +1,68 @@
-use crate::generate_conf::file_struct::{Commit, ConfigFile};
+use crate::generate_conf::file_struct::{ConfigFile, Provider, Service};
 use crate::log;
+use crate::run_deployer::provider::{self, Commit, SourceProvider};
+use crate::run_deployer::{control::ReloadRequest, load, webhook::Webhooks};
 use build::build;
 use chrono::{prelude::DateTime, Local};
-use git2::build::RepoBuilder;
-use git2::{Cred, FetchOptions, RemoteCallbacks, Repository};
-use reqwest::{Client, Response};
+pub use failure::Failure;
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{FetchOptions, Oid, Repository, SubmoduleUpdateOptions};
+use reqwest::Client;
+use schedule::Schedule;
+use std::fs;
 use std::path::PathBuf;
+use std::process::ExitStatus;
 use std::{error::Error, fmt::Display, path::Path};
-use tokio::time::{self, Duration};
+use tokio::sync::mpsc;
+use tokio::time;
 
 mod build;
-mod svc;
+mod failure;
+pub mod gc;
+mod lfs;
+pub mod record;
+mod schedule;
+pub mod svc;
 
 /// Local struct. Used to pass
 /// these three fields across functions.
-pub struct RepositoryInfo<'a> {
+#[derive(Debug, PartialEq)]
+pub struct RepositoryInfo {
+    pub provider: Provider,
+    /// API endpoint returning the latest commit.
     pub url: String,
-    pub author: &'a str,
-    pub name: &'a str,
+    pub clone_url: String,
+    pub branch: String,
+    pub author: String,
+    pub name: String,
+}
+
+/// What has been deployed so far.
+#[derive(Default)]
+struct Deployed {
+    last_commit: String,
+    /// Where `last_commit` was pulled to.
+    pull_path: Option<PathBuf>,
 }
 
-struct ServiceInfo<'a> {
+pub struct ServiceInfo<'a> {
     pub name: &'a str,
     pub filename: &'a str,
-    pub sys_dir: &'a str,
+    pub sys_dir: &'a Path,
     pub file_contents: &'a [String],
 }
 
+impl<'a> ServiceInfo<'a> {
+    pub fn new(service: &'a Service, sys_dir: &'a Path) -> Self {
+        ServiceInfo {
+            name: &service.name,
+            filename: &service.svc_filename,
+            sys_dir,
+            file_contents: &service.svc_file_contents,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum FolderFormatError {
     FailedToFormat,
@@ -45,95 +81,248 @@ impl Display for FolderFormatError {
     }
 }
 
-/// This function makes request to the GitHub's REST API.
+/// This function makes request to the provider's REST API.
 /// Also builds "services" that are specified in the config file.
-pub async fn ping<'a>(
-    config: &ConfigFile,
-    repository: &RepositoryInfo<'a>,
-) -> Result<(), Box<dyn Error>> {
+/// Between requests it waits for `reloads`, swapping in the
+/// config re-read from `config_path`, and for `webhooks`,
+/// checking the branch again right away.
+pub async fn ping(
+    config_path: &str,
+    mut config: ConfigFile,
+    mut repository: RepositoryInfo,
+    mut reloads: mpsc::Receiver<ReloadRequest>,
+    mut webhooks: Webhooks,
+) -> Result<(), Failure> {
     let client = Client::new();
-    let mut last_commit = String::from("");
+    let mut deployed = Deployed::default();
+    let mut source = provider::from_config(&config, &repository);
+    let mut schedule = Schedule::new(&config, webhooks.is_listening());
+    // See `Failure::from_head`
+    let mut first_check = true;
     loop {
-        // Make request
-        let res = send_request(&repository.url, &config.token, &client).await?;
-
-        // Panic if an error occurred
-        if !res.status().is_success() {
-            let msg: String = format!("Failed to fetch data: {}", res.status());
-            if res.status() == 401 {
-                panic!("{}", msg);
+        let checked = match source.head(&client).await {
+            Ok(commit) if deployed.last_commit != commit.sha => {
+                match source.prepare(&client).await {
+                    Ok(()) => pull_logic(&mut deployed, &config, &commit, source.as_ref()),
+                    Err(e) => Err(Failure::from_head(e, first_check)),
+                }
             }
-            continue;
-        }
+            Ok(_) => Ok(()),
+            Err(e) => Err(Failure::from_head(e, first_check)),
+        };
+        first_check = false;
 
-        let body = res.text().await?;
-        let response: Commit = serde_json::from_str(&body)?;
+        let delay = match &checked {
+            Err(Failure::RateLimited(wait)) => *wait,
+            Err(failure) if failure.needs_backoff() => {
+                schedule.failure();
+                schedule.next_delay()
+            }
+            _ => {
+                schedule.success();
+                schedule.next_delay()
+            }
+        };
+        match checked {
+            Err(failure) if failure.is_fatal() => return Err(failure),
+            Err(failure) => {
+                log!(
+                    "{}, checking again in {} seconds.",
+                    failure,
+                    delay.as_secs()
+                );
+            }
+            Ok(()) => (),
+        }
+        tokio::select! {
+            _ = time::sleep(delay) => (),
+            // The pushed commit itself may be stale if deliveries are
+            // replayed or out of order, so the branch is asked again
+            _ = webhooks.recv() => (),
+            Some(request) = reloads.recv() => {
+                let outcome = reload(config_path, &mut config, &mut repository, &mut deployed);
+                first_check = outcome.is_ok();
+                source = provider::from_config(&config, &repository);
+                webhooks.update(&config);
+                schedule.update(&config, webhooks.is_listening());
+                if let Some(reply) = request.reply {
+                    _ = reply.send(outcome);
+                }
+            }
+        }
+    }
+}
 
-        // Check for new commits
-        if last_commit != response.sha {
-            pull_logic(&mut last_commit, config, &response, repository)?;
+/// Re-reads and validates the config file and swaps it in.
+/// The current config is kept if the new one is invalid.
+///
+/// Services that have not changed are not redeployed. New or
+/// changed ones are deployed from the last pulled commit. If the
+/// repository or branch has changed, everything is deployed again
+/// on the next request.
+fn reload(
+    config_path: &str,
+    config: &mut ConfigFile,
+    repository: &mut RepositoryInfo,
+    deployed: &mut Deployed,
+) -> Result<(), String> {
+    let (new_config, new_repository) = load(config_path).map_err(|e| {
+        log!(
+            "Failed to reload the config, keeping the current one: {}",
+            e
+        );
+        e.to_string()
+    })?;
+
+    if new_repository != *repository {
+        log!("Repository or branch has changed, it will be deployed from scratch.");
+        *deployed = Deployed::default();
+    } else if let Some(pull_path) = &deployed.pull_path {
+        let sys_svc_dir_changed = new_config.sys_svc_dir != config.sys_svc_dir;
+        let changed = new_config
+            .services
+            .iter()
+            .filter(|s| sys_svc_dir_changed || !config.services.contains(s));
+        for service in changed {
+            log!("Service {} has changed, redeploying it.", service.name);
+            let sys_dir = &new_config.sys_svc_dir;
+            if let Err(e) = deploy_service(pull_path, service, sys_dir, &deployed.last_commit) {
+                log!("Failed to redeploy service {}: {}", service.name, e);
+            }
         }
-        time::sleep(Duration::from_secs(60)).await;
     }
+
+    *config = new_config;
+    *repository = new_repository;
+    log!("Configuration reloaded.");
+    Ok(())
 }
 
 /// This huge thing is (basically) core of the program.
 /// This function is where all the stuff going on:
 /// pull, build, service files logic
 fn pull_logic(
-    last_commit: &mut String,
+    deployed: &mut Deployed,
     config: &ConfigFile,
-    response: &Commit,
-    repository: &RepositoryInfo,
-) -> Result<(), Box<dyn Error>> {
-    last_commit.clear();
-    last_commit.push_str(response.sha.as_str());
-    let url = format!(
-        "https://github.com/{}/{}.git",
-        repository.author, repository.name
-    );
-
-    let pull_dir = format!("{}/{}", config.pull_dir, get_time());
-    let pull_path = pull_repository(&url, &pull_dir, &config.token)?;
-    let path = Path::new(&pull_path);
-
-    for i in 0..config.services.len() {
-        let build_dir = Path::new(&config.services[i].build_dir);
-        let custom_dir = config.services[i].custom_dir.as_ref();
-        let service_path = fmt_dir(path, custom_dir);
-        let service_info = ServiceInfo {
-            filename: &config.services[i].svc_filename,
-            name: &config.services[i].name,
-            sys_dir: &config.sys_svc_dir,
-            file_contents: &config.services[i].svc_file_contents,
-        };
+    commit: &Commit,
+    source: &dyn SourceProvider,
+) -> Result<(), Failure> {
+    log!("New commit {}", commit);
+    // Not marked as deployed if it fails, so it is pulled again on the next check
+    let pull_path = if config.persistent_clone {
+        let clone_dir = config.pull_dir.join(clone_dir_name(source.clone_url()));
+        fetch_repository(source, &clone_dir, config, &commit.sha)?;
+        clone_dir
+    } else {
+        let pull_dir = config.pull_dir.join(get_time());
+        let pull_dir = pull_dir.to_str().ok_or(Failure::Config(String::from(
+            "Pull directory is not valid UTF-8",
+        )))?;
+        PathBuf::from(pull_repository(source, pull_dir, config, &commit.sha)?)
+    };
+    deployed.last_commit.clear();
+    deployed.last_commit.push_str(commit.sha.as_str());
+    deployed.pull_path = None;
+    // Pulling the same commit again would not help
+    lfs::check(&pull_path)?;
+    deployed.pull_path = Some(pull_path.clone());
+
+    // One broken service does not stop the others from being deployed
+    let mut failed = Vec::new();
+    for service in &config.services {
+        if let Err(e) = deploy_service(&pull_path, service, &config.sys_svc_dir, &commit.sha) {
+            log!("Failed to deploy service {}: {}", service.name, e);
+            failed.push(service.name.as_str());
+        }
+    }
+    if failed.is_empty() {
+        remove_old_pulls(config, deployed.pull_path.as_deref());
+        Ok(())
+    } else {
+        Err(Failure::Deploy(format!(
+            "{} not deployed, waiting for the next commit",
+            failed.join(", ")
+        )))
+    }
+}
 
-        build_logic(service_path.as_path(), build_dir, &service_info)?;
+/// Removes pull directories `keep_pulls` does not keep,
+/// except for `pull_path` and the ones services were built
+/// from. Failing to do so is only logged.
+fn remove_old_pulls(config: &ConfigFile, pull_path: Option<&Path>) {
+    let Some(keep) = &config.keep_pulls else {
+        return;
+    };
+    let mut in_use = gc::in_use(config);
+    in_use.extend(pull_path.map(Path::to_path_buf));
+    match gc::collect(&config.pull_dir, keep, &in_use) {
+        Ok(removed) if !removed.is_empty() => {
+            log!("Removed {} old pull directories.", removed.len());
+        }
+        Ok(_) => (),
+        Err(e) => {
+            log!("Failed to remove old pull directories: {}", e);
+        }
     }
-    Ok(())
+}
+
+/// Builds one service from the pulled repository
+/// and restarts it.
+fn deploy_service(
+    pull_path: &Path,
+    service: &Service,
+    sys_svc_dir: &Path,
+    sha: &str,
+) -> Result<(), Box<dyn Error>> {
+    let build_dir = &service.build_dir;
+    let service_path = fmt_dir(pull_path, service.custom_dir.as_ref());
+    let service_info = ServiceInfo::new(service, sys_svc_dir);
+
+    build_logic(
+        pull_path,
+        service_path.as_path(),
+        build_dir,
+        &service_info,
+        sha,
+    )
 }
 
 fn build_logic(
+    pull_path: &Path,
     service_path: &Path,
     build_dir: &Path,
     svc: &ServiceInfo,
+    sha: &str,
 ) -> Result<(), Box<dyn Error>> {
-    build(service_path, &build_dir, svc.name)?;
-    let svc_path = Path::new(svc.sys_dir);
-    let status = svc::restart_service(svc.filename, svc_path, svc.file_contents);
+    // Recorded only once the build is in place
+    build(service_path, build_dir, svc.name)?;
+    if let Err(e) = record::write(build_dir, svc.name, sha, pull_path) {
+        log!("Failed to record deployment of {}: {}", svc.name, e);
+    }
+    _ = restart_logic(svc);
+    Ok(())
+}
+
+/// Restarts the service (creating its unit file if needed)
+/// and logs if it failed. Used after every deploy and by
+/// `deployer restart`.
+pub fn restart_logic(svc: &ServiceInfo) -> std::io::Result<ExitStatus> {
+    let status = svc::restart_service(svc.filename, svc.sys_dir, svc.file_contents);
 
-    if let Ok(s) = status {
-        if !s.success() {
+    match &status {
+        Ok(s) if !s.success() => {
             log!(
                 "Failed to restart service {} (status code {}).",
                 svc.filename,
                 s.code().unwrap_or(1)
             );
         }
-    } else {
-        log!("Failed to restart service {}.", svc.filename);
+        Err(_) => {
+            log!("Failed to restart service {}.", svc.filename);
+        }
+        _ => (),
     }
-    Ok(())
+    status
 }
 
 // Appends `dir` (the directory with keyfile within project)
@@ -147,46 +336,285 @@ fn fmt_dir(path: &Path, dir: Option<&String>) -> PathBuf {
     }
 }
 
-async fn send_request(url: &str, token: &str, client: &Client) -> Result<Response, reqwest::Error> {
-    let fmt_token = format!("token {}", token);
-    let response = client
-        .get(url)
-        .header("Authorization", fmt_token)
-        .header("User-Agent", "request")
-        .send()
-        .await?;
-    Ok(response)
-}
-
-fn pull_repository(url: &str, root_dir: &str, token: &str) -> Result<String, Box<dyn Error>> {
+/// Clones `branch` from the config into `root_dir` (or next
+/// to it if it exists) and detaches at `sha`. Returns where it
+/// was cloned.
+fn pull_repository(
+    source: &dyn SourceProvider,
+    root_dir: &str,
+    config: &ConfigFile,
+    sha: &str,
+) -> Result<String, Failure> {
     let root_path: &Path = Path::new(root_dir);
-
-    // Auth callback
-    let mut callback = RemoteCallbacks::new();
-    callback.credentials(move |_, _, _| Cred::userpass_plaintext("x-access-token", token));
-
-    let mut rb = RepoBuilder::new();
-    let mut fetch_options: FetchOptions<'_> = FetchOptions::new();
-
-    fetch_options.remote_callbacks(callback);
-    rb.fetch_options(fetch_options);
+    let url = source.clone_url();
+    let mut rb = repo_builder(source, config);
 
     // Pull repository
-    match rb.clone(url, root_path) {
-        Ok(_) => {
-            log!("Fetched from remote branch to {}", root_dir);
-            Ok(root_dir.to_string())
-        }
+    let (repo, dest) = match rb.clone(url, root_path) {
+        Ok(repo) => (repo, root_dir.to_string()),
         Err(e) => match e.code() {
             git2::ErrorCode::Exists => {
-                let new_dest = update_destination(true, root_dir.to_owned(), 1)?;
+                let new_dest = update_destination(true, root_dir.to_owned(), 1)
+                    .map_err(|e| Failure::Pull(e.to_string()))?;
                 log!("updated destination: {}", new_dest);
-                Repository::clone(url, &new_dest)?;
-                Ok(new_dest)
+                match rb.clone(url, Path::new(&new_dest)) {
+                    Ok(repo) => (repo, new_dest),
+                    Err(e) => {
+                        remove_partial_clone(Path::new(&new_dest));
+                        return Err(Failure::from(e));
+                    }
+                }
+            }
+            _ => {
+                remove_partial_clone(root_path);
+                return Err(Failure::from(e));
+            }
+        },
+    };
+    log!("Fetched from remote branch {} to {}", config.branch, dest);
+    if let Err(failure) = checkout(&repo, source, config, sha) {
+        remove_partial_clone(Path::new(&dest));
+        return Err(failure);
+    }
+    Ok(dest)
+}
+
+/// Removes what a failed pull left in `dest`, so it is not
+/// mistaken for a pull that can be deployed or kept.
+fn remove_partial_clone(dest: &Path) {
+    if !dest.exists() {
+        return;
+    }
+    if let Err(e) = fs::remove_dir_all(dest) {
+        log!("Failed to remove partial clone {}: {}", dest.display(), e);
+    }
+}
+
+/// Brings the persistent clone in `path` to `sha`: fetches
+/// into it and checks the commit out, discarding any local
+/// changes. Clones the repository if it is missing, and again
+/// if the clone is corrupt, but not if the remote cannot be
+/// reached. Anything else found in `path` is left alone.
+fn fetch_repository(
+    source: &dyn SourceProvider,
+    path: &Path,
+    config: &ConfigFile,
+    sha: &str,
+) -> Result<(), Failure> {
+    if !path.exists() {
+        let repo = repo_builder(source, config).clone(source.clone_url(), path)?;
+        return checkout(&repo, source, config, sha);
+    }
+    let repo = Repository::open(path).map_err(|_| {
+        Failure::Config(format!(
+            "{} is not a clone of the repository, move it out of the way",
+            path.display()
+        ))
+    })?;
+    // Only a clone of this repository is safe to remove
+    let owned = repo
+        .find_remote("origin")
+        .is_ok_and(|remote| remote.url() == Some(source.clone_url()));
+    let repo = match fetch(source, &repo, config) {
+        Ok(()) => repo,
+        Err(e) => match Failure::from(e) {
+            failure @ (Failure::Network(_) | Failure::Auth(_)) => return Err(failure),
+            failure if owned => {
+                log!(
+                    "Clone in {} is unusable ({}), cloning it again.",
+                    path.display(),
+                    failure
+                );
+                drop(repo);
+                fs::remove_dir_all(path).map_err(|e| {
+                    Failure::Pull(format!("failed to remove {}: {}", path.display(), e))
+                })?;
+                repo_builder(source, config).clone(source.clone_url(), path)?
+            }
+            failure => {
+                return Err(Failure::Config(format!(
+                    "{} is unusable ({}) and not a clone of {}, move it out of the way",
+                    path.display(),
+                    failure,
+                    source.clone_url()
+                )))
             }
-            _ => Err(Box::new(e)),
         },
+    };
+    checkout(&repo, source, config, sha)
+}
+
+/// Fetches `branch` into the clone.
+fn fetch(
+    source: &dyn SourceProvider,
+    repo: &Repository,
+    config: &ConfigFile,
+) -> Result<(), git2::Error> {
+    // Follows `repository` of the reloaded config
+    repo.remote_set_url("origin", source.clone_url())?;
+    let mut remote = repo.find_remote("origin")?;
+    let refspec = format!("+refs/heads/{0}:refs/remotes/origin/{0}", config.branch);
+    remote.fetch(&[refspec], Some(&mut fetch_options(source, config)), None)
+}
+
+/// Detaches HEAD at `sha`, the commit that was detected rather
+/// than whatever the tip of `branch` is by now, and resets the
+/// working tree to it, removing files a previous build left behind.
+/// With `sparse_checkout`, only the paths the services need are
+/// checked out. Submodules are updated as well if enabled.
+fn checkout(
+    repo: &Repository,
+    source: &dyn SourceProvider,
+    config: &ConfigFile,
+    sha: &str,
+) -> Result<(), Failure> {
+    let commit = Oid::from_str(sha)
+        .and_then(|oid| repo.find_commit(oid))
+        .map_err(|_| {
+            Failure::Pull(format!(
+                "commit {} was not fetched from branch {}",
+                sha, config.branch
+            ))
+        })?;
+    let paths = sparse_paths(config);
+    let mut checkout = CheckoutBuilder::new();
+    checkout.force().remove_untracked(true);
+    for path in paths.iter().flatten() {
+        checkout.path(path);
+    }
+    repo.checkout_tree(commit.as_object(), Some(&mut checkout))?;
+    repo.set_head_detached(commit.id())?;
+    let dir = repo.workdir().unwrap_or(repo.path());
+    log!("Checked out {} in {}", sha, dir.display());
+
+    if config.submodules {
+        update_submodules(repo, source.clone_url(), source, paths.as_deref())?;
+    } else if repo.submodules().is_ok_and(|s| !s.is_empty()) {
+        log!("The repository has submodules, set `submodules` in the config to check them out.");
+    }
+    Ok(())
+}
+
+/// Initialises and updates submodules of `repo` cloned from
+/// `repo_url` recursively, fetching them with the provider's
+/// credentials if they are on the repository's host and without
+/// them otherwise. Only the ones within `paths` (see `sparse_paths`)
+/// if they are set.
+fn update_submodules(
+    repo: &Repository,
+    repo_url: &str,
+    source: &dyn SourceProvider,
+    paths: Option<&[&str]>,
+) -> Result<(), git2::Error> {
+    for mut submodule in repo.submodules()? {
+        let path = submodule.path().to_path_buf();
+        let needed = paths.is_none_or(|paths| {
+            paths
+                .iter()
+                .any(|p| path.starts_with(p) || Path::new(p).starts_with(&path))
+        });
+        if !needed {
+            continue;
+        }
+        let mut fetch_options = FetchOptions::new();
+        let url = resolve_url(repo_url, submodule.url().unwrap_or_default());
+        fetch_options.remote_callbacks(source.callbacks(&url));
+        let mut checkout = CheckoutBuilder::new();
+        checkout.force();
+        let mut options = SubmoduleUpdateOptions::new();
+        options.fetch(fetch_options).checkout(checkout);
+        submodule.update(true, Some(&mut options))?;
+        log!("Updated submodule {}", path.display());
+        update_submodules(&submodule.open()?, &url, source, None)?;
+    }
+    Ok(())
+}
+
+/// Resolves relative `url` of a submodule (`./lib.git`,
+/// `../lib.git`) against `base`, the URL of its parent,
+/// the way git does. Other URLs are returned as they are.
+fn resolve_url(base: &str, url: &str) -> String {
+    if !url.starts_with("./") && !url.starts_with("../") {
+        return url.to_owned();
+    }
+    let mut base = base.trim_end_matches('/');
+    let mut url = url;
+    // `:` of scp-like `host:repo.git` is kept if it is reached
+    let mut separator = '/';
+    loop {
+        if let Some(rest) = url.strip_prefix("./") {
+            url = rest;
+        } else if let Some(rest) = url.strip_prefix("../") {
+            url = rest;
+            let end = base.rfind(['/', ':']).unwrap_or(0);
+            separator = if base[end..].starts_with(':') {
+                ':'
+            } else {
+                '/'
+            };
+            base = &base[..end];
+        } else {
+            break;
+        }
+    }
+    format!("{}{}{}", base, separator, url)
+}
+
+/// Paths checked out with `sparse_checkout`: `custom_dir` of
+/// every service and `shared_paths`. `None` if the whole
+/// repository is checked out.
+fn sparse_paths(config: &ConfigFile) -> Option<Vec<&str>> {
+    if !config.sparse_checkout {
+        return None;
+    }
+    let custom_dirs = config
+        .services
+        .iter()
+        .filter_map(|s| s.custom_dir.as_deref());
+    let mut paths: Vec<&str> = custom_dirs
+        .chain(config.shared_paths.iter().map(String::as_str))
+        .map(|path| path.trim_matches('/'))
+        .collect();
+    paths.sort_unstable();
+    paths.dedup();
+    Some(paths)
+}
+
+/// Directory of the persistent clone, named after the repository,
+/// e.g. `.deployer-clone-repo` for `https://host/owner/repo.git`.
+/// The prefix keeps it apart from anything else in `pull_dir`.
+fn clone_dir_name(clone_url: &str) -> String {
+    let name = clone_url
+        .trim_end_matches('/')
+        .rsplit(['/', ':'])
+        .next()
+        .unwrap_or_default();
+    let name = name.strip_suffix(".git").unwrap_or(name);
+    let name = if name.is_empty() { "repository" } else { name };
+    format!(".deployer-clone-{}", name)
+}
+
+/// Clone of `branch` with the provider's credentials. Files
+/// are not checked out, that is left to `checkout`.
+fn repo_builder<'a>(source: &'a dyn SourceProvider, config: &ConfigFile) -> RepoBuilder<'a> {
+    let mut no_checkout = CheckoutBuilder::new();
+    no_checkout.dry_run();
+    let mut rb = RepoBuilder::new();
+    rb.branch(&config.branch)
+        .fetch_options(fetch_options(source, config))
+        .with_checkout(no_checkout);
+    rb
+}
+
+/// Fetch with the provider's credentials,
+/// only the latest commit with `shallow_clone`.
+fn fetch_options<'a>(source: &'a dyn SourceProvider, config: &ConfigFile) -> FetchOptions<'a> {
+    let mut fetch_options = FetchOptions::new();
+    fetch_options.remote_callbacks(source.callbacks(source.clone_url()));
+    if config.shallow_clone {
+        fetch_options.depth(1);
     }
+    fetch_options
 }
 
 /// Recursive function. Checks if directory already exists
@@ -236,7 +664,7 @@ fn destination_fmt(
         "{}_{}_{}_{}",
         base_path[0], base_path[1], base_path[2], base_path[3]
     );
-    path.push_str("_");
+    path.push('_');
     if index < 10 {
         path.push('0');
     }
@@ -246,12 +674,14 @@ fn destination_fmt(
 
 fn get_time() -> String {
     let now: DateTime<Local> = Local::now();
-    now.format("%d_%b_%Y_%H%M").to_string()
+    now.format(gc::NAME_FMT).to_string()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::run_deployer::provider::mock::Mock;
+    use crate::run_deployer::stub;
 
     #[test]
     fn test_non_existent_path() {
@@ -275,4 +705,303 @@ mod tests {
         // Ensure the format is correctly updated
         assert_eq!(result.unwrap(), "01_Sep_2024_1307_01");
     }
+
+    #[test]
+    fn test_pull_from_file_remote() {
+        let tmp = stub::TempDir::new("pull");
+        let remote = tmp.join("remote");
+        let sha = stub::init_repo(&remote, "main");
+        let source = Mock::new(&[], format!("file://{}", remote.display()));
+
+        let pull_dir = tmp.join("01_Sep_2024_1308");
+        let config = ConfigFile::default();
+        let pulled = pull_repository(&source, pull_dir.to_str().unwrap(), &config, &sha).unwrap();
+        let repo = git2::Repository::open(&pulled).unwrap();
+        assert_eq!(repo.head().unwrap().target().unwrap().to_string(), sha);
+        assert!(Path::new(&pulled).join("README.md").exists());
+    }
+
+    #[test]
+    fn test_pull_detected_commit_of_branch() {
+        let tmp = stub::TempDir::new("detach");
+        let remote = tmp.join("remote");
+        stub::init_repo(&remote, "main");
+        let detected = stub::commit(&remote, "dev", "app.txt", "v1");
+        // Pushed after the commit was detected
+        stub::commit(&remote, "dev", "app.txt", "v2");
+        let source = Mock::new(&[], format!("file://{}", remote.display()));
+        let config = ConfigFile {
+            branch: String::from("dev"),
+            ..Default::default()
+        };
+
+        let pull_dir = tmp.join("01_Sep_2024_1308");
+        let pulled = pull_repository(&source, pull_dir.to_str().unwrap(), &config, &detected);
+        let repo = git2::Repository::open(pulled.unwrap()).unwrap();
+        assert!(repo.head_detached().unwrap());
+        assert_eq!(repo.head().unwrap().target().unwrap().to_string(), detected);
+        let app = std::fs::read_to_string(repo.workdir().unwrap().join("app.txt"));
+        assert_eq!(app.unwrap(), "v1");
+
+        // Not on the branch that was cloned
+        let pull_dir = tmp.join("02_Sep_2024_1308");
+        let missing = "0123456789012345678901234567890123456789";
+        let pulled = pull_repository(&source, pull_dir.to_str().unwrap(), &config, missing);
+        assert!(matches!(pulled, Err(Failure::Pull(_))));
+        // Nothing half-pulled is left behind
+        assert!(!pull_dir.exists());
+    }
+
+    #[test]
+    fn test_sparse_checkout() {
+        let tmp = stub::TempDir::new("sparse");
+        let remote = tmp.join("remote");
+        stub::init_repo(&remote, "main");
+        stub::commit(&remote, "main", "services/api/go.mod", "module api");
+        stub::commit(&remote, "main", "services/web/go.mod", "module web");
+        let sha = stub::commit(&remote, "main", "libs/common/lib.go", "package common");
+        let source = Mock::new(&[], format!("file://{}", remote.display()));
+        let config = ConfigFile {
+            sparse_checkout: true,
+            shared_paths: vec![String::from("libs/common/")],
+            services: vec![Service {
+                custom_dir: Some(String::from("services/api")),
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        assert_eq!(
+            sparse_paths(&config).unwrap(),
+            vec!["libs/common", "services/api"]
+        );
+
+        let pull_dir = tmp.join("01_Sep_2024_1308");
+        let pulled = pull_repository(&source, pull_dir.to_str().unwrap(), &config, &sha);
+        let pulled = PathBuf::from(pulled.unwrap());
+        assert!(pulled.join("services/api/go.mod").exists());
+        assert!(pulled.join("libs/common/lib.go").exists());
+        assert!(!pulled.join("services/web").exists());
+        assert!(!pulled.join("README.md").exists());
+    }
+
+    #[tokio::test]
+    async fn test_pull_logic_with_mock_provider() {
+        let tmp = stub::TempDir::new("mock");
+        let remote = tmp.join("remote");
+        let sha = stub::init_repo(&remote, "main");
+        let source = Mock::new(&[&sha], format!("file://{}", remote.display()));
+        let config = ConfigFile {
+            pull_dir: tmp.join("pulls"),
+            services: Vec::new(),
+            ..Default::default()
+        };
+
+        let mut deployed = Deployed::default();
+        let commit = source.head(&Client::new()).await.unwrap();
+        pull_logic(&mut deployed, &config, &commit, &source).unwrap();
+        assert_eq!(deployed.last_commit, sha);
+        let pull_path = deployed.pull_path.unwrap();
+        assert!(pull_path.starts_with(tmp.join("pulls")));
+        assert!(pull_path.join("README.md").exists());
+
+        // Nothing left to hand out
+        let next = source.head(&Client::new()).await;
+        assert!(matches!(
+            next.map_err(Failure::from),
+            Err(Failure::Config(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_clone_is_retried() {
+        let tmp = stub::TempDir::new("retry");
+        let missing = tmp.join("missing");
+        let source = Mock::new(&["abc123"], format!("file://{}", missing.display()));
+        let config = ConfigFile {
+            pull_dir: tmp.join("pulls"),
+            services: Vec::new(),
+            ..Default::default()
+        };
+
+        let mut deployed = Deployed::default();
+        let commit = source.head(&Client::new()).await.unwrap();
+        let failure = pull_logic(&mut deployed, &config, &commit, &source).unwrap_err();
+        assert!(!failure.is_fatal());
+        assert!(failure.needs_backoff());
+        // The same commit is deployed again on the next check
+        assert!(deployed.last_commit.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_persistent_clone() {
+        let tmp = stub::TempDir::new("persist");
+        let remote = tmp.join("remote.git");
+        let first = stub::init_repo(&remote, "main");
+        let second = stub::commit(&remote, "main", "app.txt", "v2");
+        let source = Mock::new(&[&first, &second], format!("file://{}", remote.display()));
+        let config = ConfigFile {
+            pull_dir: tmp.join("pulls"),
+            persistent_clone: true,
+            services: Vec::new(),
+            ..Default::default()
+        };
+        let clone = tmp.join("pulls").join(".deployer-clone-remote");
+        let head = |path: &Path| {
+            let repo = git2::Repository::open(path).unwrap();
+            let head = repo.head().unwrap().target().unwrap();
+            head.to_string()
+        };
+
+        let mut deployed = Deployed::default();
+        let commit = source.head(&Client::new()).await.unwrap();
+        pull_logic(&mut deployed, &config, &commit, &source).unwrap();
+        assert_eq!(deployed.pull_path.as_deref(), Some(clone.as_path()));
+        assert_eq!(head(&clone), first);
+        assert!(!clone.join("app.txt").exists());
+
+        // Fetched into the same directory, leftovers are removed
+        fs::write(clone.join("build.log"), "leftover").unwrap();
+        let commit = source.head(&Client::new()).await.unwrap();
+        pull_logic(&mut deployed, &config, &commit, &source).unwrap();
+        assert_eq!(head(&clone), second);
+        assert!(clone.join("app.txt").exists());
+        assert!(!clone.join("build.log").exists());
+
+        // Broken clone is replaced by a fresh one
+        let git_dir = clone.join(".git");
+        fs::write(git_dir.join("marker"), "").unwrap();
+        fs::write(git_dir.join("refs/remotes/origin/main"), "corrupt").unwrap();
+        fetch_repository(&source, &clone, &config, &first).unwrap();
+        assert_eq!(head(&clone), first);
+        assert!(!git_dir.join("marker").exists());
+
+        // But a clone of another repository is not removed
+        let other = Mock::new(&[], format!("file://{}", tmp.join("other.git").display()));
+        let failure = fetch_repository(&other, &clone, &config, &first).unwrap_err();
+        assert!(failure.is_fatal());
+        assert!(git_dir.exists());
+
+        // Nor is a directory that is not a clone at all
+        fs::remove_dir_all(&git_dir).unwrap();
+        fs::write(clone.join("data.txt"), "keep").unwrap();
+        let failure = fetch_repository(&source, &clone, &config, &first).unwrap_err();
+        assert!(failure.is_fatal());
+        assert!(clone.join("data.txt").exists());
+    }
+
+    #[test]
+    fn test_failed_build_is_not_recorded() {
+        let tmp = stub::TempDir::new("failed-build");
+        let pull_path = tmp.join("pull");
+        fs::create_dir_all(&pull_path).unwrap();
+        fs::write(pull_path.join("Cargo.toml"), "not a manifest").unwrap();
+        let service = Service {
+            name: String::from("app"),
+            build_dir: tmp.join("build"),
+            ..Default::default()
+        };
+        fs::create_dir_all(&service.build_dir).unwrap();
+
+        assert!(deploy_service(&pull_path, &service, &tmp, "abc123").is_err());
+        assert!(record::read(&service.build_dir, "app").is_none());
+    }
+
+    #[test]
+    fn test_resolve_url() {
+        let base = "https://github.com/owner/repo.git";
+        assert_eq!(
+            resolve_url(base, "../lib.git"),
+            "https://github.com/owner/lib.git"
+        );
+        assert_eq!(
+            resolve_url(base, "./lib.git"),
+            "https://github.com/owner/repo.git/lib.git"
+        );
+        assert_eq!(
+            resolve_url(base, "../../other/lib.git"),
+            "https://github.com/other/lib.git"
+        );
+        assert_eq!(
+            resolve_url("git@github.com:repo.git", "../lib.git"),
+            "git@github.com:lib.git"
+        );
+        // Relative to the parent submodule, which may be elsewhere
+        let parent = "ssh://git@git.example.com:2222/team/proto.git";
+        assert_eq!(
+            resolve_url(parent, "../lib.git"),
+            "ssh://git@git.example.com:2222/team/lib.git"
+        );
+        assert_eq!(
+            resolve_url(base, "https://gitlab.com/lib.git"),
+            "https://gitlab.com/lib.git"
+        );
+    }
+
+    #[test]
+    fn test_clone_dir_name() {
+        assert_eq!(
+            clone_dir_name("https://github.com/owner/repo.git"),
+            ".deployer-clone-repo"
+        );
+        assert_eq!(
+            clone_dir_name("git@github.com:owner/repo.git"),
+            ".deployer-clone-repo"
+        );
+        assert_eq!(
+            clone_dir_name("file:///srv/git/app/"),
+            ".deployer-clone-app"
+        );
+        assert_eq!(clone_dir_name(""), ".deployer-clone-repository");
+    }
+
+    #[test]
+    fn test_submodules() {
+        let tmp = stub::TempDir::new("submodule");
+        let proto = tmp.join("proto");
+        stub::init_repo(&proto, "main");
+        stub::commit(&proto, "main", "api.proto", "syntax = \"proto3\";");
+        let remote = tmp.join("remote");
+        stub::init_repo(&remote, "main");
+        let url = format!("file://{}", proto.display());
+        let sha = stub::add_submodule(&remote, "main", &url, "vendor/proto");
+        let source = Mock::new(&[], format!("file://{}", remote.display()));
+
+        let pull_dir = tmp.join("01_Sep_2024_1308");
+        let config = ConfigFile::default();
+        let pulled = pull_repository(&source, pull_dir.to_str().unwrap(), &config, &sha);
+        let pulled = PathBuf::from(pulled.unwrap());
+        assert!(!pulled.join("vendor/proto/api.proto").exists());
+
+        let pull_dir = tmp.join("02_Sep_2024_1308");
+        let config = ConfigFile {
+            submodules: true,
+            ..Default::default()
+        };
+        let pulled = pull_repository(&source, pull_dir.to_str().unwrap(), &config, &sha);
+        let pulled = PathBuf::from(pulled.unwrap());
+        assert!(pulled.join("vendor/proto/api.proto").exists());
+    }
+
+    #[tokio::test]
+    async fn test_lfs_pointers_are_not_deployed() {
+        let tmp = stub::TempDir::new("pointer");
+        let remote = tmp.join("remote");
+        stub::init_repo(&remote, "main");
+        let pointer = "version https://git-lfs.github.com/spec/v1\nsize 12345\n";
+        let sha = stub::commit(&remote, "main", "assets/logo.png", pointer);
+        let source = Mock::new(&[&sha], format!("file://{}", remote.display()));
+        let config = ConfigFile {
+            pull_dir: tmp.join("pulls"),
+            services: Vec::new(),
+            ..Default::default()
+        };
+
+        let mut deployed = Deployed::default();
+        let commit = source.head(&Client::new()).await.unwrap();
+        let failure = pull_logic(&mut deployed, &config, &commit, &source).unwrap_err();
+        assert!(failure.to_string().contains("assets/logo.png"));
+        // Waits for the next commit instead of pulling this one again
+        assert_eq!(deployed.last_commit, sha);
+        assert!(deployed.pull_path.is_none());
+    }
 }
@@ -8,7 +8,7 @@ use project_trait::{Go, Project, Rust};
 use std::process::Command;
 use std::{
     fmt::Display,
-    io::{Error, ErrorKind, Result},
+    io::{Error, Result},
     path::Path,
     process::ExitStatus,
 };
@@ -67,12 +67,10 @@ pub fn build(service_path: &Path, build_dir: &Path, service_name: &str) -> Resul
     match key_file.1 {
         KeyFile::Rust => {
             let rust = Rust::new();
-            let status = rust.build(path);
+            let status = rust.build(path)?;
 
-            log!(
-                "Build command has finished with status: {}",
-                status.expect("Failed to get exit status code")
-            );
+            log!("Build command has finished with status: {}", status);
+            check(status, "cargo build")?;
 
             let p = path.to_str().expect("Failed to get rust build path");
             let rs_build_path = format!("{}/{}", p, rust.get_build_dir());
@@ -80,15 +78,11 @@ pub fn build(service_path: &Path, build_dir: &Path, service_name: &str) -> Resul
         }
         KeyFile::Go => {
             let /* me */ go /* it's 12:30AM already :( */ = Go::new();
-            let status = go.build(path);
-            let project_name = go
-                .get_build_name(key_file.0.path())
-                .expect("Failed to find Go module name");
+            let status = go.build(path)?;
+            let project_name = go.get_build_name(key_file.0.path())?;
 
-            log!(
-                "Build command has finished with {}",
-                status.expect("Failed to get exit status code")
-            );
+            log!("Build command has finished with {}", status);
+            check(status, "go build")?;
 
             let go_build_path = if let Some(module) = project_name {
                 format!(
@@ -131,23 +125,24 @@ pub fn build(service_path: &Path, build_dir: &Path, service_name: &str) -> Resul
 }
 
 /// Moves and renames the build.
-fn move_build(project: &Path, destination: &Path, service_name: &str) -> Result<ExitStatus> {
+fn move_build(project: &Path, destination: &Path, service_name: &str) -> Result<()> {
     let tmp = format!("{}/{}", destination.to_str().unwrap(), service_name);
     let destination = Path::new(&tmp);
     if Path::exists(destination) {
-        let mut cmd = Command::new("rm")
-            .arg("-rf")
-            .arg(destination)
-            .spawn()
-            .expect("Failed to remove existing service.");
-        cmd.wait()?;
+        let status = Command::new("rm").arg("-rf").arg(destination).status()?;
+        check(status, "Removing the previous build")?;
+    }
+    let status = Command::new("mv").arg(project).arg(destination).status()?;
+    check(status, "Moving the build")
+}
+
+/// Turns unsuccessful `status` of `command` into an error.
+fn check(status: ExitStatus, command: &str) -> Result<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(Error::other(format!("{command} has failed with {status}")))
     }
-    let mut cmd = Command::new("mv")
-        .arg(project)
-        .arg(destination)
-        .spawn()
-        .expect("Failed to move release");
-    cmd.wait()
 }
 
 /// Search for supported `KeyFiles`.
@@ -166,8 +161,26 @@ fn list_directories(path: &Path) -> Result<(DirEntry, KeyFile)> {
             }
         }
     }
-    Err(Error::new(
-        ErrorKind::Other,
-        "Couldn't find any supported key-file.",
-    ))
+    Err(Error::other("Couldn't find any supported key-file."))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_deployer::stub;
+    use std::fs;
+
+    #[test]
+    fn test_failed_build_is_an_error() {
+        let project = stub::TempDir::new("build-project");
+        let build_dir = stub::TempDir::new("build-output");
+        fs::write(project.join(CARGO), "not a manifest").unwrap();
+
+        assert!(build(&project, &build_dir, "service").is_err());
+        assert!(!build_dir.join("service").exists());
+
+        // Nothing to move either
+        let missing = project.join("target");
+        assert!(move_build(&missing, &build_dir, "service").is_err());
+    }
 }
@@ -4,7 +4,7 @@ use std::path::Path;
 use std::process::{Command, ExitStatus};
 
 pub trait Project {
-    /// Panics if fails to spawn the CMD.
+    /// Fails if the command cannot be started.
     fn build(&self, current_dir: &Path) -> Result<ExitStatus>;
     fn get_build_dir(&self) -> &str;
 }
@@ -28,8 +28,7 @@ impl<'a> Project for Rust<'a> {
             .arg("build")
             .arg("--release")
             .current_dir(current_dir)
-            .spawn()
-            .expect("Failed to build Rust project");
+            .spawn()?;
         cmd.wait()
     }
 
@@ -70,8 +69,7 @@ impl<'a> Project for Go<'a> {
             .arg("build")
             .arg(".")
             .current_dir(current_dir)
-            .spawn()
-            .expect("Failed to build Go project");
+            .spawn()?;
         cmd.wait()
     }
 
@@ -0,0 +1,143 @@
+// What can go wrong while checking for new commits and
+// deploying them. Decides whether Deployer retries, keeps
+// running or gives up.
+
+use crate::run_deployer::provider::HeadError;
+use git2::{ErrorClass, ErrorCode};
+use reqwest::StatusCode;
+use std::error::Error;
+use std::fmt::Display;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub enum Failure {
+    /// The remote could not be reached or failed
+    /// to answer. Retried with backoff.
+    Network(String),
+    /// Credentials were refused. Retried with backoff,
+    /// reloading the config may fix it.
+    Auth(String),
+    /// The provider answered with something unexpected.
+    /// Retried with backoff.
+    Parse(String),
+    /// The commit could not be cloned or checked out. The partial
+    /// clone is removed and it is retried with backoff.
+    Pull(String),
+    /// Building failed or the pulled commit cannot be deployed.
+    /// Not retried until the next commit.
+    Deploy(String),
+    /// Retried once the rate limit is reset, without backoff.
+    RateLimited(Duration),
+    /// Nothing is going to change without fixing
+    /// the config file, Deployer exits.
+    Config(String),
+}
+
+impl Failure {
+    pub fn is_fatal(&self) -> bool {
+        matches!(self, Self::Config(_))
+    }
+
+    /// Whether the next check should be delayed more.
+    pub fn needs_backoff(&self) -> bool {
+        matches!(
+            self,
+            Self::Network(_) | Self::Auth(_) | Self::Parse(_) | Self::Pull(_)
+        )
+    }
+
+    /// Failure of asking the provider. Rejected credentials and
+    /// a missing repository or branch (401, 404) are fatal only
+    /// on the `first` check after start or reload. Once the config
+    /// has been accepted, they are retried like refused credentials,
+    /// e.g. while the token is being rotated.
+    pub fn from_head(e: HeadError, first: bool) -> Self {
+        match Self::from(e) {
+            Self::Config(e) if !first => Self::Auth(e),
+            failure => failure,
+        }
+    }
+}
+
+impl Error for Failure {}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Network(e) => write!(f, "Network error: {}", e),
+            Self::Auth(e) => write!(f, "Authentication error: {}", e),
+            Self::Parse(e) => write!(f, "Unexpected response: {}", e),
+            Self::Pull(e) => write!(f, "Pull failed: {}", e),
+            Self::Deploy(e) => write!(f, "Deploy failed: {}", e),
+            Self::RateLimited(wait) => {
+                write!(f, "Rate limit exhausted for {} seconds", wait.as_secs())
+            }
+            Self::Config(e) => write!(f, "Configuration error: {}", e),
+        }
+    }
+}
+
+impl From<HeadError> for Failure {
+    fn from(e: HeadError) -> Self {
+        match e {
+            HeadError::Status(StatusCode::UNAUTHORIZED) => Self::Config(String::from(
+                "the provider rejected the credentials (401 Unauthorized)",
+            )),
+            HeadError::Status(StatusCode::NOT_FOUND) => Self::Config(String::from(
+                "repository or branch not found (404 Not Found)",
+            )),
+            HeadError::Status(status) if status.is_client_error() => {
+                Self::Auth(format!("request refused ({})", status))
+            }
+            HeadError::Status(status) => Self::Network(format!("provider replied {}", status)),
+            HeadError::Request(e) if e.is_decode() => Self::Parse(e.to_string()),
+            HeadError::Request(e) => Self::Network(e.to_string()),
+            HeadError::Parse(e) => Self::Parse(e.to_string()),
+            HeadError::Git(e) if e.code() == ErrorCode::NotFound => Self::Config(e.to_string()),
+            HeadError::Git(e) => Self::from(e),
+            HeadError::Auth(e) => Self::Auth(e),
+            HeadError::RateLimited(wait) => Self::RateLimited(wait),
+        }
+    }
+}
+
+impl From<git2::Error> for Failure {
+    fn from(e: git2::Error) -> Self {
+        match (e.code(), e.class()) {
+            (ErrorCode::Auth | ErrorCode::Certificate, _) => Self::Auth(e.to_string()),
+            (_, ErrorClass::Net | ErrorClass::Http | ErrorClass::Ssh | ErrorClass::Ssl) => {
+                Self::Network(e.to_string())
+            }
+            _ => Self::Pull(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classification() {
+        let fatal = Failure::from(HeadError::Status(StatusCode::UNAUTHORIZED));
+        assert!(fatal.is_fatal());
+        // Only on the first check
+        let later = Failure::from_head(HeadError::Status(StatusCode::NOT_FOUND), false);
+        assert!(matches!(later, Failure::Auth(_)));
+        assert!(later.needs_backoff());
+
+        let unavailable = Failure::from(HeadError::Status(StatusCode::BAD_GATEWAY));
+        assert!(matches!(unavailable, Failure::Network(_)));
+        assert!(unavailable.needs_backoff());
+
+        let refused = git2::Error::new(ErrorCode::Auth, ErrorClass::Ssh, "denied");
+        assert!(matches!(Failure::from(refused), Failure::Auth(_)));
+        let timeout = git2::Error::new(ErrorCode::GenericError, ErrorClass::Net, "timed out");
+        assert!(matches!(Failure::from(timeout), Failure::Network(_)));
+        let corrupt = git2::Error::new(ErrorCode::GenericError, ErrorClass::Odb, "corrupt");
+        assert!(Failure::from(corrupt).needs_backoff());
+
+        let limited = Failure::from(HeadError::RateLimited(Duration::from_secs(5)));
+        assert!(!limited.needs_backoff() && !limited.is_fatal());
+    }
+}
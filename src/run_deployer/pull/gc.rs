@@ -0,0 +1,231 @@
+// Removal of old pull directories as `keep_pulls` says. Only
+// directories named the way `pull_logic` names them
+// (`DD_Mon_YYYY_HHMM[_NN]`) are ever touched.
+
+use super::record;
+use crate::generate_conf::file_struct::{ConfigFile, KeepPulls};
+use crate::run_deployer::deserialise;
+use chrono::{Local, NaiveDateTime};
+use std::cmp::Reverse;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Names of pull directories, see `get_time` in `pull`.
+pub const NAME_FMT: &str = "%d_%b_%Y_%H%M";
+
+/// Pull directory created by Deployer.
+struct Pull {
+    path: PathBuf,
+    /// Time in the name of the directory.
+    created: NaiveDateTime,
+    /// `_NN` postfix added by `update_destination`, 0 if there is none.
+    index: u32,
+}
+
+/// Removes pull directories in `pull_dir` that are not kept by
+/// `keep`. The newest one and `in_use` are never removed.
+///
+/// Returns the removed directories.
+pub fn collect(pull_dir: &Path, keep: &KeepPulls, in_use: &[PathBuf]) -> io::Result<Vec<PathBuf>> {
+    let mut pulls = list(pull_dir)?;
+    // Newest first
+    pulls.sort_by_key(|pull| Reverse((pull.created, pull.index)));
+    let now = Local::now().naive_local();
+
+    let mut removed = Vec::new();
+    for (i, pull) in pulls.into_iter().enumerate() {
+        let over_count = keep.count.is_some_and(|count| i >= count);
+        let age = u64::try_from((now - pull.created).num_seconds()).unwrap_or(0);
+        let too_old = keep
+            .max_age_days
+            .is_some_and(|days| age > days.saturating_mul(24 * 60 * 60));
+        if i == 0 || !(over_count || too_old) || in_use.contains(&pull.path) {
+            continue;
+        }
+        fs::remove_dir_all(&pull.path)?;
+        removed.push(pull.path);
+    }
+    Ok(removed)
+}
+
+/// Pull directories the services are currently built from,
+/// as the records of their deploys say.
+pub fn in_use(config: &ConfigFile) -> Vec<PathBuf> {
+    config
+        .services
+        .iter()
+        .filter_map(|service| record::read(&service.build_dir, &service.name)?.pull_path)
+        .collect()
+}
+
+/// Pull directories in `pull_dir`. Files, symlinks and
+/// directories named otherwise are left out.
+fn list(pull_dir: &Path) -> io::Result<Vec<Pull>> {
+    let mut pulls = Vec::new();
+    for entry in fs::read_dir(pull_dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name();
+        if let Some((created, index)) = name.to_str().and_then(parse_name) {
+            pulls.push(Pull {
+                path: entry.path(),
+                created,
+                index,
+            });
+        }
+    }
+    Ok(pulls)
+}
+
+/// Time and index of a `DD_Mon_YYYY_HHMM[_NN]` name,
+/// `None` for names Deployer does not create.
+fn parse_name(name: &str) -> Option<(NaiveDateTime, u32)> {
+    let (time, index) = match name.match_indices('_').nth(3) {
+        Some((at, _)) => {
+            let index = &name[at + 1..];
+            if index.len() < 2 || !index.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            (&name[..at], index.parse().ok()?)
+        }
+        None => (name, 0),
+    };
+    let created = NaiveDateTime::parse_from_str(time, NAME_FMT).ok()?;
+    // Rejects names parsed leniently, e.g. `1_sep_2024_1308`
+    (created.format(NAME_FMT).to_string() == time).then_some((created, index))
+}
+
+/// Removes old pull directories as `keep_pulls` in the config
+/// says, the same way it is done after every deploy.
+///
+/// Returns exit code to be returned by the process.
+pub fn run(config_path: &str) -> i32 {
+    let config = match deserialise(config_path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return 1;
+        }
+    };
+    let Some(keep) = &config.keep_pulls else {
+        println!("`keep_pulls` is not set in the config file, all pulls are kept.");
+        return 1;
+    };
+    match collect(&config.pull_dir, keep, &in_use(&config)) {
+        Ok(removed) => {
+            for path in &removed {
+                println!("Removed {}", path.display());
+            }
+            println!("{} old pull directories removed.", removed.len());
+            0
+        }
+        Err(e) => {
+            println!(
+                "Failed to remove old pulls from {}: {}",
+                config.pull_dir.display(),
+                e
+            );
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_conf::file_struct::Service;
+    use crate::run_deployer::stub;
+    use chrono::Duration;
+
+    fn pull_name(days_ago: i64, index: u32) -> String {
+        let created = Local::now().naive_local() - Duration::days(days_ago);
+        let name = created.format(NAME_FMT).to_string();
+        match index {
+            0 => name,
+            i => format!("{}_{:02}", name, i),
+        }
+    }
+
+    #[test]
+    fn test_parse_name() {
+        let (created, index) = parse_name("01_Sep_2024_1308").unwrap();
+        assert_eq!(created.to_string(), "2024-09-01 13:08:00");
+        assert_eq!(index, 0);
+        assert_eq!(parse_name("01_Sep_2024_1308_12").unwrap().1, 12);
+
+        for name in [
+            "01_Sep_2024",
+            "1_Sep_2024_1308",
+            "01_sep_2024_1308",
+            "01_Sep_2024_1308_1",
+            "01_Sep_2024_1308_ab",
+            "my-service",
+        ] {
+            assert_eq!(parse_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_collect() {
+        let dir = stub::TempDir::new("gc");
+        let pulls = [
+            pull_name(0, 0),
+            pull_name(0, 1),
+            pull_name(2, 0),
+            pull_name(40, 0),
+            pull_name(50, 0),
+        ];
+        for name in &pulls {
+            fs::create_dir_all(dir.join(name)).unwrap();
+        }
+        // Not created by Deployer
+        let file = dir.join(pull_name(60, 0));
+        fs::create_dir_all(dir.join("repository")).unwrap();
+        fs::write(&file, "").unwrap();
+
+        // Removed for its age, but in use
+        let in_use = dir.join(&pulls[3]);
+        let keep = KeepPulls {
+            count: None,
+            max_age_days: Some(30),
+        };
+        let removed = collect(&dir, &keep, &[in_use]).unwrap();
+        assert_eq!(removed, vec![dir.join(&pulls[4])]);
+
+        let keep = KeepPulls {
+            count: Some(2),
+            max_age_days: None,
+        };
+        let mut removed = collect(&dir, &keep, &[]).unwrap();
+        removed.sort();
+        let mut expected = vec![dir.join(&pulls[2]), dir.join(&pulls[3])];
+        expected.sort();
+        assert_eq!(removed, expected);
+        // `_01` is newer than the one without postfix
+        assert!(dir.join(&pulls[0]).exists() && dir.join(&pulls[1]).exists());
+        assert!(dir.join("repository").exists());
+        assert!(file.exists());
+    }
+
+    #[test]
+    fn test_in_use_from_records() {
+        let dir = stub::TempDir::new("gc-records");
+        let pull = dir.join(pull_name(40, 0));
+        record::write(&dir, "api", "abc123", &pull).unwrap();
+        let service = |name: &str| Service {
+            name: name.to_owned(),
+            build_dir: dir.to_path_buf(),
+            ..Default::default()
+        };
+        let config = ConfigFile {
+            // `web` has never been deployed
+            services: vec![service("api"), service("web")],
+            ..Default::default()
+        };
+        assert_eq!(in_use(&config), vec![pull]);
+    }
+}
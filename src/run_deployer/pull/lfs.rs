@@ -0,0 +1,101 @@
+// Detection of Git LFS pointer files. Deployer does not fetch
+// LFS objects, so a repository using LFS is checked out with
+// small text files in place of the real assets.
+
+use super::Failure;
+use git2::Repository;
+use std::error::Error;
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+/// First line of every pointer file.
+const POINTER_PREFIX: &[u8] = b"version https://git-lfs.github.com/spec/";
+
+/// Pointer files are never larger than this.
+const MAX_POINTER_SIZE: u64 = 1024;
+
+/// Mode of submodules in the index.
+const GITLINK: u32 = 0o160000;
+
+/// Fails if there are LFS pointer files in the checkout in
+/// `dir` (submodules included), listing the first of them.
+/// Only files tracked by git are looked at, not the output
+/// of earlier builds or ignored files.
+pub fn check(dir: &Path) -> Result<(), Failure> {
+    let pointers = Repository::open(dir)
+        .map_err(Box::from)
+        .and_then(|repo| find_pointers(&repo))
+        .map_err(|e| Failure::Deploy(format!("failed to look for LFS pointer files: {}", e)))?;
+    let Some(first) = pointers.first() else {
+        return Ok(());
+    };
+    Err(Failure::Deploy(format!(
+        "{} Git LFS pointer file(s) found instead of the real files (e.g. {}), \
+        Deployer does not fetch LFS objects",
+        pointers.len(),
+        first.display()
+    )))
+}
+
+/// Paths of the tracked pointer files relative to the working
+/// tree of `repo`, looking into checked out submodules. Files
+/// that are not checked out (see `sparse_checkout`) are skipped.
+fn find_pointers(repo: &Repository) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+    let Some(workdir) = repo.workdir() else {
+        return Ok(Vec::new());
+    };
+    let mut pointers = Vec::new();
+    for entry in repo.index()?.iter() {
+        let path = Path::new(OsStr::from_bytes(&entry.path));
+        if entry.mode == GITLINK {
+            if let Ok(submodule) = Repository::open(workdir.join(path)) {
+                let nested = find_pointers(&submodule)?;
+                pointers.extend(nested.into_iter().map(|p| path.join(p)));
+            }
+            continue;
+        }
+        let Ok(metadata) = fs::symlink_metadata(workdir.join(path)) else {
+            continue;
+        };
+        if !metadata.is_file() || metadata.len() > MAX_POINTER_SIZE {
+            continue;
+        }
+        let mut start = [0; POINTER_PREFIX.len()];
+        let mut file = File::open(workdir.join(path))?;
+        if file.read_exact(&mut start).is_ok() && start == POINTER_PREFIX {
+            pointers.push(path.to_path_buf());
+        }
+    }
+    Ok(pointers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_deployer::stub;
+    use std::fs;
+
+    #[test]
+    fn test_pointer_files_are_detected() {
+        let dir = stub::TempDir::new("lfs");
+        stub::init_repo(&dir, "main");
+        let pointer = "version https://git-lfs.github.com/spec/v1\n\
+            oid sha256:4d7a214614ab2935c943f9e0ff69d22eadbb8f32b1258daaa5e2ca24d17e2393\n\
+            size 12345\n";
+        // Build output and ignored files are not looked at
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("target").join("logo.png"), pointer).unwrap();
+        assert!(check(&dir).is_ok());
+
+        stub::commit(&dir, "main", "assets/logo.png", pointer);
+        let repo = Repository::open(&*dir).unwrap();
+        assert_eq!(
+            find_pointers(&repo).unwrap(),
+            vec![Path::new("assets").join("logo.png")]
+        );
+        assert!(matches!(check(&dir), Err(Failure::Deploy(_))));
+    }
+}
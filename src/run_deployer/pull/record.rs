@@ -0,0 +1,34 @@
+// Records of deployed builds. Each service gets
+// `build_dir/.<name>.deployer.json` next to its build,
+// so the record survives the build being replaced.
+
+use crate::generate_conf::file_struct::Deployment;
+use chrono::Local;
+use std::{
+    fs,
+    io::Result,
+    path::{Path, PathBuf},
+};
+
+/// Saves `sha` as the commit currently deployed
+/// for `service_name`, built from `pull_path`.
+pub fn write(build_dir: &Path, service_name: &str, sha: &str, pull_path: &Path) -> Result<()> {
+    let deployment = Deployment {
+        sha: sha.to_owned(),
+        deployed_at: Local::now().to_rfc3339(),
+        pull_path: Some(pull_path.to_owned()),
+    };
+    let data = serde_json::to_string_pretty(&deployment)?;
+    fs::write(record_path(build_dir, service_name), data)
+}
+
+/// Reads the record of the last deploy of `service_name`.
+/// Returns `None` if the service has never been deployed.
+pub fn read(build_dir: &Path, service_name: &str) -> Option<Deployment> {
+    let data = fs::read(record_path(build_dir, service_name)).ok()?;
+    serde_json::from_slice(&data).ok()
+}
+
+fn record_path(build_dir: &Path, service_name: &str) -> PathBuf {
+    build_dir.join(format!(".{}.deployer.json", service_name))
+}
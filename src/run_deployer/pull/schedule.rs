@@ -0,0 +1,120 @@
+// When to ask the provider for new commits next: every
+// `poll_interval` with random jitter, so a fleet of hosts does
+// not poll in sync, and less and less often while it fails.
+
+use crate::generate_conf::file_struct::ConfigFile;
+use rand::Rng;
+use tokio::time::Duration;
+
+/// How often the provider is asked for new commits
+/// if `poll_interval` is not set.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Same when pushes come through webhooks, it is
+/// only a fallback for the missed ones then.
+const WEBHOOK_POLL_INTERVAL: Duration = Duration::from_secs(600);
+
+/// Backoff does not grow beyond this (unless
+/// `poll_interval` itself is longer).
+const MAX_BACKOFF: Duration = Duration::from_secs(3600);
+
+/// Delays are randomly shortened or prolonged by up to 10%.
+const JITTER: f64 = 0.1;
+
+pub struct Schedule {
+    interval: Duration,
+    failures: u32,
+}
+
+impl Schedule {
+    pub fn new(config: &ConfigFile, webhooks: bool) -> Self {
+        Schedule {
+            interval: interval(config, webhooks),
+            failures: 0,
+        }
+    }
+
+    /// Applies `poll_interval` of the reloaded config.
+    pub fn update(&mut self, config: &ConfigFile, webhooks: bool) {
+        self.interval = interval(config, webhooks);
+    }
+
+    pub fn success(&mut self) {
+        self.failures = 0;
+    }
+
+    pub fn failure(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+    }
+
+    /// Time to wait before the next check.
+    pub fn next_delay(&self) -> Duration {
+        let factor = rand::thread_rng().gen_range(-JITTER..=JITTER);
+        let secs = self.base_delay().as_secs_f64() * (1.0 + factor);
+        // Overflows only with an absurd `poll_interval`
+        Duration::try_from_secs_f64(secs).unwrap_or(MAX_BACKOFF)
+    }
+
+    /// `interval`, doubled with every failure in a row.
+    fn base_delay(&self) -> Duration {
+        let max = MAX_BACKOFF.max(self.interval);
+        let multiplier = 2u32.saturating_pow(self.failures.min(16));
+        self.interval.saturating_mul(multiplier).min(max)
+    }
+}
+
+fn interval(config: &ConfigFile, webhooks: bool) -> Duration {
+    match config.poll_interval {
+        Some(secs) => Duration::from_secs(secs),
+        None if webhooks => WEBHOOK_POLL_INTERVAL,
+        None => POLL_INTERVAL,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_resets_after_success() {
+        let config = ConfigFile {
+            poll_interval: Some(30),
+            ..Default::default()
+        };
+        let mut schedule = Schedule::new(&config, false);
+        assert_eq!(schedule.base_delay(), Duration::from_secs(30));
+
+        schedule.failure();
+        schedule.failure();
+        assert_eq!(schedule.base_delay(), Duration::from_secs(120));
+        for _ in 0..100 {
+            schedule.failure();
+        }
+        assert_eq!(schedule.base_delay(), MAX_BACKOFF);
+
+        schedule.success();
+        assert_eq!(schedule.base_delay(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn test_huge_poll_interval() {
+        let config = ConfigFile {
+            poll_interval: Some(u64::MAX),
+            ..Default::default()
+        };
+        let mut schedule = Schedule::new(&config, false);
+        schedule.failure();
+        for _ in 0..100 {
+            assert!(schedule.next_delay() >= MAX_BACKOFF);
+        }
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let schedule = Schedule::new(&ConfigFile::default(), true);
+        for _ in 0..100 {
+            let delay = schedule.next_delay();
+            assert!(delay >= Duration::from_secs(540) && delay <= Duration::from_secs(660));
+        }
+    }
+}
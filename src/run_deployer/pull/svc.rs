@@ -6,7 +6,7 @@ use crate::log;
 use chrono::{prelude::DateTime, Local};
 use std::{
     fs,
-    io::{ErrorKind, Result, Write},
+    io::{self, ErrorKind, Result, Write},
     path::{Path, PathBuf},
     process::{Command, ExitStatus},
 };
@@ -23,35 +23,157 @@ pub fn restart_service(
     let dir = complete_dir(svc_dir, svc_filename);
     match dir.exists() {
         false => {
-            let mut file = fs::OpenOptions::new().create(true).append(true).open(dir)?;
-            for s in contents {
-                _ = file.write(&s.as_bytes());
-                _ = file.write(b"\n");
-            }
-            let cmd = Command::new("systemctl")
-                .arg("start")
-                .arg(svc_filename)
-                .spawn();
-            if let Ok(mut c) = cmd {
-                c.wait()
-            } else {
-                log!("Failed to use `systemctl` command.");
-                Err(ErrorKind::Unsupported.into())
-            }
+            write_unit(&dir, contents)?;
+            systemctl("start", svc_filename)
         }
-        true => {
-            let cmd = Command::new("systemctl")
-                .arg("restart")
-                .arg(svc_filename)
-                .spawn();
-            if let Ok(mut c) = cmd {
-                c.wait()
-            } else {
-                log!("Failed to use `systemctl` command.");
-                Err(ErrorKind::Unsupported.into())
+        true => systemctl("restart", svc_filename),
+    }
+}
+
+/// Starts `svc_filename` service. If its unit file
+/// does not exist in `svc_dir` yet, it is written
+/// from `contents` first.
+pub fn start_service(
+    svc_filename: &str,
+    svc_dir: &Path,
+    contents: &[String],
+) -> Result<ExitStatus> {
+    let dir = complete_dir(svc_dir, svc_filename);
+    if !dir.exists() {
+        write_unit(&dir, contents)?;
+    }
+    systemctl("start", svc_filename)
+}
+
+/// State of a unit as reported by `systemctl show`.
+#[derive(Debug, Default, PartialEq)]
+pub struct UnitStatus {
+    /// active, inactive, failed, etc.
+    pub active_state: String,
+    /// running, dead, exited, etc.
+    pub sub_state: String,
+    /// `None` if the service has no running process.
+    pub main_pid: Option<u32>,
+    /// Unix timestamp of the moment unit entered active state,
+    /// `None` unless it is still active.
+    pub active_since: Option<i64>,
+}
+
+/// Queries the service manager for the state of `svc_filename`.
+pub fn service_status(svc_filename: &str) -> Result<UnitStatus> {
+    let output = Command::new("systemctl")
+        .arg("show")
+        .arg(svc_filename)
+        .arg("--timestamp=unix")
+        .arg("--property=ActiveState,SubState,MainPID,ActiveEnterTimestamp")
+        .output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::other(
+            String::from_utf8_lossy(&output.stderr).trim().to_owned(),
+        ));
+    }
+    Ok(parse_status(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parses `KEY=value` lines printed by `systemctl show`.
+fn parse_status(output: &str) -> UnitStatus {
+    let mut status = UnitStatus::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once('=') else {
+            continue;
+        };
+        match key {
+            "ActiveState" => status.active_state = value.to_owned(),
+            "SubState" => status.sub_state = value.to_owned(),
+            "MainPID" => status.main_pid = value.parse().ok().filter(|pid| *pid != 0),
+            "ActiveEnterTimestamp" => {
+                status.active_since = value.strip_prefix('@').and_then(|t| t.parse().ok())
             }
+            _ => continue,
         }
     }
+    // systemd keeps the timestamp after the unit stops
+    if status.active_state != "active" {
+        status.active_since = None;
+    }
+    status
+}
+
+/// Stops `svc_filename` service.
+pub fn stop_service(svc_filename: &str) -> Result<ExitStatus> {
+    systemctl("stop", svc_filename)
+}
+
+/// Returns what the unit file of `svc_filename` in `svc_dir`
+/// currently contains or `None` if it does not exist.
+pub fn read_unit(svc_filename: &str, svc_dir: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(complete_dir(svc_dir, svc_filename)) {
+        Ok(data) => Ok(Some(data)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Joins `svc_file_contents` lines the way
+/// they are written into the unit file.
+pub fn unit_contents(contents: &[String]) -> String {
+    contents.iter().map(|s| format!("{}\n", s)).collect()
+}
+
+/// Replaces the unit file with `contents`, keeping the previous
+/// one as `<svc_filename>.<YYYYMMDDHHMMSS>.bak`. Existing backups
+/// are never overwritten. Does not reload the service manager,
+/// call `daemon_reload` afterwards.
+pub fn overwrite_unit(svc_filename: &str, svc_dir: &Path, contents: &[String]) -> Result<()> {
+    let dir = complete_dir(svc_dir, svc_filename);
+    if dir.exists() {
+        let time = Local::now().format("%Y%m%d%H%M%S");
+        let backup = complete_dir(svc_dir, &format!("{}.{}.bak", svc_filename, time));
+        let mut from = fs::File::open(&dir)?;
+        let mut to = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&backup)?;
+        io::copy(&mut from, &mut to)?;
+        log!("Backed up {} to {}", dir.display(), backup.display());
+    }
+    fs::write(&dir, unit_contents(contents))?;
+    log!("Overwritten unit file {}", dir.display());
+    Ok(())
+}
+
+/// Makes the service manager re-read all unit files.
+pub fn daemon_reload() -> Result<ExitStatus> {
+    systemctl("daemon-reload", "")
+}
+
+/// Creates the unit file and makes the service
+/// manager pick it up.
+fn write_unit(path: &Path, contents: &[String]) -> Result<()> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(unit_contents(contents).as_bytes())?;
+    log!("Created unit file {}", path.display());
+    daemon_reload()?;
+    Ok(())
+}
+
+/// Runs `systemctl <action> <svc_filename>` and waits for it.
+/// Empty `svc_filename` runs the action without a unit.
+fn systemctl(action: &str, svc_filename: &str) -> Result<ExitStatus> {
+    let mut cmd = Command::new("systemctl");
+    cmd.arg(action);
+    if !svc_filename.is_empty() {
+        cmd.arg(svc_filename);
+    }
+    if let Ok(mut c) = cmd.spawn() {
+        c.wait()
+    } else {
+        log!("Failed to use `systemctl` command.");
+        Err(ErrorKind::Unsupported.into())
+    }
 }
 
 fn complete_dir(svc_dir: &Path, svc_filename: &str) -> PathBuf {
@@ -59,3 +181,58 @@ fn complete_dir(svc_dir: &Path, svc_filename: &str) -> PathBuf {
     let assembled = format!("{}/{}", dir_str, svc_filename);
     PathBuf::from(assembled)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::run_deployer::stub;
+
+    #[test]
+    fn test_overwrite_unit_keeps_backup() {
+        let dir = stub::TempDir::new("overwrite");
+        fs::write(dir.join("app.service"), "old\n").unwrap();
+        overwrite_unit("app.service", &dir, &[String::from("new")]).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.join("app.service")).unwrap(),
+            "new\n"
+        );
+
+        let backups: Vec<_> = fs::read_dir(&*dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| name.ends_with(".bak"))
+            .collect();
+        assert_eq!(backups.len(), 1);
+        assert!(backups[0].starts_with("app.service."));
+        assert_eq!(fs::read_to_string(dir.join(&backups[0])).unwrap(), "old\n");
+    }
+
+    #[test]
+    fn test_parse_running_status() {
+        let output = "MainPID=1234\nActiveState=active\nSubState=running\nActiveEnterTimestamp=@1725537600\n";
+        let status = parse_status(output);
+        assert_eq!(status.active_state, "active");
+        assert_eq!(status.sub_state, "running");
+        assert_eq!(status.main_pid, Some(1234));
+        assert_eq!(status.active_since, Some(1725537600));
+    }
+
+    #[test]
+    fn test_parse_inactive_status() {
+        let output = "MainPID=0\nActiveState=inactive\nSubState=dead\nActiveEnterTimestamp=\n";
+        let status = parse_status(output);
+        assert_eq!(status.active_state, "inactive");
+        assert_eq!(status.main_pid, None);
+        assert_eq!(status.active_since, None);
+    }
+
+    #[test]
+    fn test_parse_failed_status() {
+        let output =
+            "MainPID=0\nActiveState=failed\nSubState=failed\nActiveEnterTimestamp=@1725537600\n";
+        let status = parse_status(output);
+        assert_eq!(status.active_state, "failed");
+        // Not running, so there is no uptime
+        assert_eq!(status.active_since, None);
+    }
+}
@@ -0,0 +1,453 @@
+// Embedded HTTP listener for GitHub push webhooks, so new
+// commits are deployed as soon as they are pushed instead of
+// on the next check. Pushes are verified with the secret from
+// the config and passed on to `ping` through a channel.
+
+use crate::generate_conf::file_struct::{ConfigFile, Secret};
+use crate::log;
+use crate::run_deployer::provider::Commit;
+use chrono::{DateTime, Local};
+use hmac::{Hmac, Mac};
+use serde_derive::Deserialize;
+use sha2::Sha256;
+use std::io;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, Duration};
+
+/// Requests with larger body are refused.
+const MAX_BODY: usize = 1024 * 1024;
+
+/// Requests with longer request line or header are refused.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// Requests with more headers are refused.
+const MAX_HEADERS: usize = 100;
+
+/// Slow clients are disconnected after this.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Which pushes are accepted. Updated when the config is reloaded.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub secret: Secret,
+    pub branch: String,
+}
+
+impl Filter {
+    fn from_config(config: &ConfigFile) -> Self {
+        Filter {
+            secret: config
+                .webhook
+                .as_ref()
+                .map(|w| w.secret.clone())
+                .unwrap_or_default(),
+            branch: config.branch.clone(),
+        }
+    }
+}
+
+/// Pushes received by the listener.
+pub struct Webhooks {
+    filter: watch::Sender<Filter>,
+    pushes: mpsc::Receiver<Commit>,
+    listening: bool,
+}
+
+impl Webhooks {
+    /// Next commit pushed to the branch. Never returns if the
+    /// listener is not running.
+    /// Pushes received in the meantime are skipped in favour
+    /// of the latest one.
+    pub async fn recv(&mut self) -> Commit {
+        let Some(mut commit) = self.pushes.recv().await else {
+            return std::future::pending().await;
+        };
+        while let Ok(newer) = self.pushes.try_recv() {
+            commit = newer;
+        }
+        commit
+    }
+
+    pub fn is_listening(&self) -> bool {
+        self.listening
+    }
+
+    /// Applies secret and branch of the reloaded config.
+    /// The address is only read on start.
+    pub fn update(&self, config: &ConfigFile) {
+        self.filter.send_replace(Filter::from_config(config));
+    }
+}
+
+/// Response to a webhook request.
+#[derive(Debug, PartialEq)]
+struct Reply {
+    status: &'static str,
+    message: &'static str,
+}
+
+impl Reply {
+    const fn new(status: &'static str, message: &'static str) -> Self {
+        Reply { status, message }
+    }
+}
+
+/// The part of push event's payload Deployer cares about.
+#[derive(Debug, Deserialize)]
+struct PushEvent {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    after: String,
+    #[serde(default)]
+    deleted: bool,
+    head_commit: Option<HeadCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct HeadCommit {
+    message: Option<String>,
+    author: Option<Author>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Author {
+    name: String,
+}
+
+/// Starts listening for webhooks on the address from
+/// the config if it is set. Failing to bind the address
+/// is logged but does not stop Deployer.
+pub fn listen(config: &ConfigFile) -> Webhooks {
+    let (filter, filter_rx) = watch::channel(Filter::from_config(config));
+    let (tx, pushes) = mpsc::channel(4);
+    let mut webhooks = Webhooks {
+        filter,
+        pushes,
+        listening: false,
+    };
+    let Some(webhook) = &config.webhook else {
+        return webhooks;
+    };
+
+    match bind(&webhook.address) {
+        Ok(listener) => {
+            log!("Listening for webhooks on {}", webhook.address);
+            tokio::spawn(accept(listener, filter_rx, tx));
+            webhooks.listening = true;
+        }
+        Err(e) => {
+            log!(
+                "Failed to listen for webhooks on {}: {}",
+                webhook.address,
+                e
+            );
+        }
+    }
+    webhooks
+}
+
+fn bind(address: &str) -> io::Result<TcpListener> {
+    let listener = std::net::TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+async fn accept(listener: TcpListener, filter: watch::Receiver<Filter>, tx: mpsc::Sender<Commit>) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        let filter = filter.clone();
+        let tx = tx.clone();
+        tokio::spawn(async move {
+            match time::timeout(TIMEOUT, handle(stream, filter, tx)).await {
+                Ok(Err(e)) => {
+                    log!("Webhook connection failed: {}", e);
+                }
+                Err(_) => {
+                    log!("Webhook connection timed out.");
+                }
+                Ok(Ok(())) => (),
+            }
+        });
+    }
+}
+
+async fn handle(
+    stream: TcpStream,
+    filter: watch::Receiver<Filter>,
+    tx: mpsc::Sender<Commit>,
+) -> io::Result<()> {
+    let (read, write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+
+    const TOO_LARGE: Reply = Reply::new(
+        "431 Request Header Fields Too Large",
+        "Request header is too large",
+    );
+    let Some(request_line) = read_line(&mut reader).await? else {
+        return respond(write, TOO_LARGE).await;
+    };
+    let mut headers = Vec::new();
+    loop {
+        let Some(line) = read_line(&mut reader).await? else {
+            return respond(write, TOO_LARGE).await;
+        };
+        if line.trim().is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return respond(write, TOO_LARGE).await;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        }
+    }
+    let header = |name: &str| {
+        headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    };
+
+    let length: usize = header("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    let reply = if !request_line.starts_with("POST ") {
+        Reply::new("405 Method Not Allowed", "Only POST is accepted")
+    } else if length > MAX_BODY {
+        Reply::new("413 Payload Too Large", "Payload is too large")
+    } else {
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).await?;
+        let event = header("x-github-event").unwrap_or_default();
+        let signature = header("x-hub-signature-256").unwrap_or_default();
+        let filter = filter.borrow().clone();
+        match receive(event, signature, &body, &filter) {
+            Ok(Some(commit)) => {
+                log!("Received push of {} to {}.", commit.sha, filter.branch);
+                if tx.try_send(commit).is_err() {
+                    log!("Too many pushes queued, the push is left to the next check.");
+                }
+                Reply::new("202 Accepted", "Deploying")
+            }
+            Ok(None) => Reply::new("200 OK", "Ignored"),
+            Err(reply) => {
+                log!("Rejected webhook: {}", reply.message);
+                reply
+            }
+        }
+    };
+    respond(write, reply).await
+}
+
+/// Reads a line of at most `MAX_LINE` bytes, `None` if it is
+/// longer. Empty at the end of the stream.
+async fn read_line(reader: &mut (impl AsyncBufRead + Unpin)) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    reader.take(MAX_LINE).read_line(&mut line).await?;
+    if line.len() as u64 == MAX_LINE && !line.ends_with('\n') {
+        return Ok(None);
+    }
+    Ok(Some(line))
+}
+
+async fn respond(mut write: OwnedWriteHalf, reply: Reply) -> io::Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        reply.status,
+        reply.message.len(),
+        reply.message
+    );
+    write.write_all(response.as_bytes()).await
+}
+
+/// Verifies the webhook and returns the commit to deploy
+/// if it is a push to the configured branch.
+fn receive(
+    event: &str,
+    signature: &str,
+    body: &[u8],
+    filter: &Filter,
+) -> Result<Option<Commit>, Reply> {
+    if !verify(signature, body, &filter.secret) {
+        return Err(Reply::new("401 Unauthorized", "Invalid signature"));
+    }
+    // `ping` is sent when the webhook is created
+    if event != "push" {
+        return Ok(None);
+    }
+    let push: PushEvent = serde_json::from_slice(body)
+        .map_err(|_| Reply::new("400 Bad Request", "Invalid push event"))?;
+    if push.deleted || push.git_ref != format!("refs/heads/{}", filter.branch) {
+        return Ok(None);
+    }
+    let head_commit = push.head_commit;
+    Ok(Some(Commit {
+        sha: push.after,
+        message: head_commit.as_ref().and_then(|c| c.message.clone()),
+        author: head_commit.and_then(|c| c.author).map(|a| a.name),
+    }))
+}
+
+/// Checks `sha256=<hex>` HMAC of the body in constant time.
+/// Nothing is accepted with an empty secret, which is what is
+/// left once `webhook` is removed from the config.
+fn verify(signature: &str, body: &[u8], secret: &Secret) -> bool {
+    if secret.expose().is_empty() {
+        return false;
+    }
+    let Some(signature) = signature.strip_prefix("sha256=") else {
+        return false;
+    };
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let Ok(mut mac) = Hmac::<Sha256>::new_from_slice(secret.expose().as_bytes()) else {
+        return false;
+    };
+    mac.update(body);
+    mac.verify_slice(&signature).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_conf::file_struct::Webhook;
+    use reqwest::Client;
+
+    const PUSH: &str = r#"{
+        "ref": "refs/heads/main",
+        "after": "abc123",
+        "head_commit": { "message": "Fix", "author": { "name": "Jane" } }
+    }"#;
+
+    fn sign(body: &str, secret: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(body.as_bytes());
+        format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+    }
+
+    fn filter(branch: &str) -> Filter {
+        Filter {
+            secret: Secret::new(String::from("secret")),
+            branch: branch.to_owned(),
+        }
+    }
+
+    #[test]
+    fn test_empty_secret_is_rejected() {
+        let filter = Filter {
+            branch: String::from("main"),
+            ..Default::default()
+        };
+        let signature = sign(PUSH, "");
+        let reply = receive("push", &signature, PUSH.as_bytes(), &filter);
+        assert_eq!(reply.unwrap_err().status, "401 Unauthorized");
+    }
+
+    #[test]
+    fn test_receive_push() {
+        let signature = sign(PUSH, "secret");
+        let commit = receive("push", &signature, PUSH.as_bytes(), &filter("main"));
+        let commit = commit.unwrap().unwrap();
+        assert_eq!(commit.sha, "abc123");
+        assert_eq!(commit.author.as_deref(), Some("Jane"));
+
+        // Other branches and events are ignored
+        let other = receive("push", &signature, PUSH.as_bytes(), &filter("dev"));
+        assert_eq!(other.unwrap(), None);
+        let ping = receive("ping", &signature, PUSH.as_bytes(), &filter("main"));
+        assert_eq!(ping.unwrap(), None);
+    }
+
+    #[test]
+    fn test_invalid_signature() {
+        let filter = filter("main");
+        for signature in [
+            sign(PUSH, "wrong"),
+            String::from("sha256=zz"),
+            String::new(),
+        ] {
+            let reply = receive("push", &signature, PUSH.as_bytes(), &filter).unwrap_err();
+            assert_eq!(reply.status, "401 Unauthorized");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_over_http() {
+        let address = {
+            let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            probe.local_addr().unwrap().to_string()
+        };
+        let config = ConfigFile {
+            webhook: Some(Webhook {
+                address: address.clone(),
+                secret: Secret::new(String::from("secret")),
+            }),
+            ..Default::default()
+        };
+        let mut webhooks = listen(&config);
+        assert!(webhooks.is_listening());
+
+        let client = Client::builder().no_proxy().build().unwrap();
+        let response = client
+            .post(format!("http://{}/", address))
+            .header("X-GitHub-Event", "push")
+            .header("X-Hub-Signature-256", sign(PUSH, "secret"))
+            .body(PUSH)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 202);
+        assert_eq!(webhooks.recv().await.sha, "abc123");
+
+        // Secret of the reloaded config is used right away
+        webhooks.update(&ConfigFile {
+            webhook: Some(Webhook {
+                address: address.clone(),
+                secret: Secret::new(String::from("rotated")),
+            }),
+            ..config
+        });
+        let response = client
+            .post(format!("http://{}/", address))
+            .header("X-GitHub-Event", "push")
+            .header("X-Hub-Signature-256", sign(PUSH, "secret"))
+            .body(PUSH)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 401);
+    }
+
+    #[tokio::test]
+    async fn test_large_header_is_refused() {
+        let address = {
+            let probe = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            probe.local_addr().unwrap().to_string()
+        };
+        let config = ConfigFile {
+            webhook: Some(Webhook {
+                address: address.clone(),
+                secret: Secret::new(String::from("secret")),
+            }),
+            ..Default::default()
+        };
+        let _webhooks = listen(&config);
+
+        let long_line = format!("X-Long: {}\r\n", "a".repeat(MAX_LINE as usize));
+        let many_headers = "X-Many: a\r\n".repeat(MAX_HEADERS + 1);
+        for headers in [long_line, many_headers] {
+            let mut stream = TcpStream::connect(&address).await.unwrap();
+            let request = format!("POST / HTTP/1.1\r\n{headers}\r\n");
+            stream.write_all(request.as_bytes()).await.unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with("HTTP/1.1 431 "), "{response}");
+        }
+    }
+}